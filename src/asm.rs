@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::path::Path;
+use crate::cpu::PROGRAM_START;
+use crate::error::AsmError;
+
+// Assembles the mnemonic syntax printed by `disasm` into a ROM, so anything
+// the disassembler writes out assembles back to the same bytes.
+//
+// Source is one statement per line, with `;` starting a comment:
+//
+//   SPEED EQU 2                ; a constant
+//   loop:                      ; a label, which may also share a line
+//       ADD V0, SPEED
+//       JP loop
+//   sprite:
+//       DB 0b11110000, 0x90, "text"
+//       DW 0x1234, sprite + 2
+//       INCLUDE "other.asm"    ; relative to the including file
+//
+// Mnemonics, registers and directives are case-insensitive; labels and
+// constants are not. Numbers are decimal, 0x hex or 0b binary, and operands
+// may add or subtract numbers, labels and constants.
+
+// Registers and keywords that cannot be used as label or constant names
+const RESERVED: [&str; 26] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "EQU",
+];
+
+// Includes nested deeper than this are assumed to be including themselves
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// A source line with includes expanded, remembering where it came from
+struct SourceLine {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+// An instruction or data directive, placed at `addr`
+struct Statement {
+    line: usize,              // Index into the source lines
+    addr: usize,
+    mnemonic: String,         // Upper case
+    operands: Vec<String>,
+}
+
+enum Symbol {
+    Label(usize),
+    Constant(String, usize),  // The unevaluated expression and its source line index
+}
+
+// An instruction operand
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    V(u16),          // A register V0-VF
+    I,
+    IndirectI,       // [I]
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(String),    // LONG followed by a 16-bit expression
+    Value(String),   // Any other expression
+}
+
+// Assembles `source` into a ROM to be loaded at 0x200. `path` names the file
+// in error messages and is where included files are looked up from.
+pub fn assemble(source: &str, path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler { lines: Vec::new(), statements: Vec::new(), symbols: HashMap::new() };
+    assembler.load(source, path, 0, None)?;
+    assembler.place()?;
+    assembler.encode()
+}
+
+struct Assembler {
+    lines: Vec<SourceLine>,
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Assembler {
+    fn error(&self, line: usize, message: String) -> AsmError {
+        let line = &self.lines[line];
+        AsmError { file: line.file.clone(), line: line.number, message }
+    }
+
+    // Appends the lines of `source`, expanding INCLUDE directives in place.
+    // `included_by` is the line index of the INCLUDE that pulled it in.
+    fn load(&mut self, source: &str, path: &Path, depth: usize, included_by: Option<usize>) -> Result<(), AsmError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(self.error(included_by.unwrap_or(0), String::from("includes are nested too deeply")));
+        }
+        for (number, text) in source.lines().enumerate() {
+            let index = self.lines.len();
+            self.lines.push(SourceLine { file: path.display().to_string(), number: number + 1, text: text.to_string() });
+
+            let (_, rest) = split_label(strip_comment(text));
+            let (word, argument) = split_word(rest);
+            if !word.eq_ignore_ascii_case("INCLUDE") {
+                continue;
+            }
+            let name = string_literal(argument)
+                .ok_or_else(|| self.error(index, String::from("INCLUDE expects a quoted file name")))?;
+            let included = path.parent().unwrap_or(Path::new("")).join(name);
+            let source = std::fs::read_to_string(&included)
+                .map_err(|error| self.error(index, format!("cannot read {}: {}", included.display(), error)))?;
+            self.load(&source, &included, depth + 1, Some(index))?;
+        }
+        Ok(())
+    }
+
+    // First pass: works out the address of every statement and label and
+    // collects the constants
+    fn place(&mut self) -> Result<(), AsmError> {
+        let mut addr = PROGRAM_START as usize;
+        for index in 0..self.lines.len() {
+            let text = strip_comment(&self.lines[index].text).to_string();
+            let (label, rest) = split_label(&text);
+            if let Some(label) = label {
+                self.define(index, label, Symbol::Label(addr))?;
+            }
+            let (word, argument) = split_word(rest);
+            if word.is_empty() || word.eq_ignore_ascii_case("INCLUDE") {
+                continue;
+            }
+
+            // NAME EQU value
+            let (second, value) = split_word(argument);
+            if second.eq_ignore_ascii_case("EQU") {
+                self.define(index, word, Symbol::Constant(value.to_string(), index))?;
+                continue;
+            }
+
+            let mnemonic = word.to_ascii_uppercase();
+            let operands: Vec<String> = if argument.is_empty() {
+                Vec::new()
+            } else {
+                split_operands(argument)
+            };
+            let size = match mnemonic.as_str() {
+                "DB" => operands.iter().map(|operand| string_literal(operand).map_or(1, str::len)).sum(),
+                "DW" => operands.len() * 2,
+                "LD" if operands.get(1).is_some_and(|operand| matches!(operand_kind(operand), Operand::Long(_))) => 4,
+                _ if MNEMONICS.contains(&mnemonic.as_str()) => 2,
+                _ => return Err(self.error(index, format!("unknown instruction '{}'", word))),
+            };
+            self.statements.push(Statement { line: index, addr, mnemonic, operands });
+            addr += size;
+            if addr > 0x10000 {
+                return Err(self.error(index, String::from("program does not fit in 64KB of memory")));
+            }
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, line: usize, name: &str, symbol: Symbol) -> Result<(), AsmError> {
+        if !is_identifier(name) || RESERVED.contains(&name.to_ascii_uppercase().as_str()) {
+            return Err(self.error(line, format!("'{}' cannot be used as a name", name)));
+        }
+        if self.symbols.insert(name.to_string(), symbol).is_some() {
+            return Err(self.error(line, format!("'{}' is already defined", name)));
+        }
+        Ok(())
+    }
+
+    // Second pass: encodes every statement now that all the labels are known
+    fn encode(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::new();
+        for statement in &self.statements {
+            debug_assert_eq!(PROGRAM_START as usize + rom.len(), statement.addr);
+            let line = statement.line;
+            match statement.mnemonic.as_str() {
+                "DB" => {
+                    for operand in &statement.operands {
+                        match string_literal(operand) {
+                            Some(text) => rom.extend_from_slice(text.as_bytes()),
+                            None => rom.push(self.value(operand, line, 0xFF)? as u8),
+                        }
+                    }
+                }
+                "DW" => {
+                    for operand in &statement.operands {
+                        rom.extend_from_slice(&self.value(operand, line, 0xFFFF)?.to_be_bytes());
+                    }
+                }
+                _ => {
+                    let operands: Vec<Operand> = statement.operands.iter().map(|operand| operand_kind(operand)).collect();
+                    for word in self.instruction(&statement.mnemonic, &operands, line)? {
+                        rom.extend_from_slice(&word.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(rom)
+    }
+
+    // The words making up one instruction
+    fn instruction(&self, mnemonic: &str, operands: &[Operand], line: usize) -> Result<Vec<u16>, AsmError> {
+        use Operand::*;
+        let addr = |expr: &str| self.value(expr, line, 0xFFF);
+        let byte = |expr: &str| self.value(expr, line, 0xFF);
+        let nibble = |expr: &str| self.value(expr, line, 0xF);
+        let xy = |x: u16, y: u16| (x << 8) | (y << 4);
+
+        let word = match (mnemonic, operands) {
+            ("CLS", []) => 0x00E0,
+            ("RET", []) => 0x00EE,
+            ("SCD", [Value(n)]) => 0x00C0 | nibble(n)?,
+            ("SCU", [Value(n)]) => 0x00D0 | nibble(n)?,
+            ("SCR", []) => 0x00FB,
+            ("SCL", []) => 0x00FC,
+            ("EXIT", []) => 0x00FD,
+            ("LOW", []) => 0x00FE,
+            ("HIGH", []) => 0x00FF,
+            ("JP", [Value(a)]) => 0x1000 | addr(a)?,
+            ("JP", [V(0), Value(a)]) => 0xB000 | addr(a)?,
+            ("CALL", [Value(a)]) => 0x2000 | addr(a)?,
+            ("SE", [V(x), Value(b)]) => 0x3000 | xy(*x, 0) | byte(b)?,
+            ("SNE", [V(x), Value(b)]) => 0x4000 | xy(*x, 0) | byte(b)?,
+            ("SE", [V(x), V(y)]) => 0x5000 | xy(*x, *y),
+            ("SAVE", [V(x), V(y)]) => 0x5002 | xy(*x, *y),
+            ("LOAD", [V(x), V(y)]) => 0x5003 | xy(*x, *y),
+            ("LD", [V(x), Value(b)]) => 0x6000 | xy(*x, 0) | byte(b)?,
+            ("ADD", [V(x), Value(b)]) => 0x7000 | xy(*x, 0) | byte(b)?,
+            ("LD", [V(x), V(y)]) => 0x8000 | xy(*x, *y),
+            ("OR", [V(x), V(y)]) => 0x8001 | xy(*x, *y),
+            ("AND", [V(x), V(y)]) => 0x8002 | xy(*x, *y),
+            ("XOR", [V(x), V(y)]) => 0x8003 | xy(*x, *y),
+            ("ADD", [V(x), V(y)]) => 0x8004 | xy(*x, *y),
+            ("SUB", [V(x), V(y)]) => 0x8005 | xy(*x, *y),
+            // Without Vy the shifts use Vx, which behaves the same with or
+            // without the shift quirk
+            ("SHR", [V(x)]) => 0x8006 | xy(*x, *x),
+            ("SHR", [V(x), V(y)]) => 0x8006 | xy(*x, *y),
+            ("SUBN", [V(x), V(y)]) => 0x8007 | xy(*x, *y),
+            ("SHL", [V(x)]) => 0x800E | xy(*x, *x),
+            ("SHL", [V(x), V(y)]) => 0x800E | xy(*x, *y),
+            ("SNE", [V(x), V(y)]) => 0x9000 | xy(*x, *y),
+            ("LD", [I, Value(a)]) => 0xA000 | addr(a)?,
+            ("LD", [I, Long(a)]) => return Ok(vec![0xF000, self.value(a, line, 0xFFFF)?]),
+            ("RND", [V(x), Value(b)]) => 0xC000 | xy(*x, 0) | byte(b)?,
+            ("DRW", [V(x), V(y), Value(n)]) => 0xD000 | xy(*x, *y) | nibble(n)?,
+            ("SKP", [V(x)]) => 0xE09E | xy(*x, 0),
+            ("SKNP", [V(x)]) => 0xE0A1 | xy(*x, 0),
+            ("PLANE", [Value(n)]) => 0xF001 | (nibble(n)? << 8),
+            ("AUDIO", []) => 0xF002,
+            ("LD", [V(x), Dt]) => 0xF007 | xy(*x, 0),
+            ("LD", [V(x), K]) => 0xF00A | xy(*x, 0),
+            ("LD", [Dt, V(x)]) => 0xF015 | xy(*x, 0),
+            ("LD", [St, V(x)]) => 0xF018 | xy(*x, 0),
+            ("ADD", [I, V(x)]) => 0xF01E | xy(*x, 0),
+            ("LD", [F, V(x)]) => 0xF029 | xy(*x, 0),
+            ("LD", [Hf, V(x)]) => 0xF030 | xy(*x, 0),
+            ("LD", [B, V(x)]) => 0xF033 | xy(*x, 0),
+            ("PITCH", [V(x)]) => 0xF03A | xy(*x, 0),
+            ("LD", [IndirectI, V(x)]) => 0xF055 | xy(*x, 0),
+            ("LD", [V(x), IndirectI]) => 0xF065 | xy(*x, 0),
+            ("LD", [R, V(x)]) => 0xF075 | xy(*x, 0),
+            ("LD", [V(x), R]) => 0xF085 | xy(*x, 0),
+            _ => return Err(self.error(line, format!("invalid operands for {}", mnemonic))),
+        };
+        Ok(vec![word])
+    }
+
+    // Evaluates an operand expression, which must come to between 0 and `max`
+    fn value(&self, expr: &str, line: usize, max: u16) -> Result<u16, AsmError> {
+        let value = self.evaluate(expr, line, 0)?;
+        if !(0..=max as i64).contains(&value) {
+            return Err(self.error(line, format!("value {} is out of range 0-{}", value, max)));
+        }
+        Ok(value as u16)
+    }
+
+    // Adds and subtracts the numbers and symbols in `expr`. `depth` counts the
+    // constants being expanded, to catch ones defined in terms of themselves.
+    fn evaluate(&self, expr: &str, line: usize, depth: usize) -> Result<i64, AsmError> {
+        let expr = expr.trim();
+        if expr.is_empty() {
+            return Err(self.error(line, String::from("missing value")));
+        }
+        let mut total = 0i64;
+        let mut sign = 1;
+        let mut rest = expr;
+        loop {
+            rest = rest.trim_start();
+            if let Some(stripped) = rest.strip_prefix('-') {
+                sign = -sign;
+                rest = stripped;
+                continue;
+            }
+            let end = rest.find(['+', '-']).unwrap_or(rest.len());
+            let term = self.term(rest[..end].trim(), line, depth)?;
+            total = term
+                .checked_mul(sign)
+                .and_then(|term| total.checked_add(term))
+                .ok_or_else(|| self.error(line, format!("value out of range in '{}'", expr)))?;
+            if end == rest.len() {
+                return Ok(total);
+            }
+            sign = if rest[end..].starts_with('-') { -1 } else { 1 };
+            rest = &rest[end + 1..];
+        }
+    }
+
+    fn term(&self, term: &str, line: usize, depth: usize) -> Result<i64, AsmError> {
+        let (digits, radix) = if let Some(hex) = term.strip_prefix("0x").or_else(|| term.strip_prefix("0X")) {
+            (hex, 16)
+        } else if let Some(binary) = term.strip_prefix("0b").or_else(|| term.strip_prefix("0B")) {
+            (binary, 2)
+        } else {
+            (term, 10)
+        };
+        if term.starts_with(|c: char| c.is_ascii_digit()) {
+            return i64::from_str_radix(digits, radix).map_err(|_| self.error(line, format!("invalid number '{}'", term)));
+        }
+        match self.symbols.get(term) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Constant(_, _)) if depth > self.symbols.len() => {
+                Err(self.error(line, format!("constant '{}' is defined in terms of itself", term)))
+            }
+            Some(Symbol::Constant(expr, defined)) => self.evaluate(expr, *defined, depth + 1),
+            None if term.is_empty() => Err(self.error(line, String::from("missing value"))),
+            None => Err(self.error(line, format!("undefined name '{}'", term))),
+        }
+    }
+}
+
+// Mnemonics taking up a single two byte word; `LD I, LONG` is checked separately
+const MNEMONICS: [&str; 31] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE",
+    "AUDIO", "PITCH",
+];
+
+// Drops a `;` comment, ignoring semicolons inside a quoted string
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return text[..i].trim(),
+            _ => {}
+        }
+    }
+    text.trim()
+}
+
+// Splits off a leading `label:`
+fn split_label(text: &str) -> (Option<&str>, &str) {
+    match text.split_once(':') {
+        Some((label, rest)) if is_identifier(label.trim()) => (Some(label.trim()), rest.trim()),
+        _ => (None, text),
+    }
+}
+
+// Splits off the first whitespace separated word
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+// Splits comma separated operands, leaving commas inside strings alone
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = vec![String::new()];
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                operands.last_mut().unwrap().push(c);
+            }
+            ',' if !quoted => operands.push(String::new()),
+            _ => operands.last_mut().unwrap().push(c),
+        }
+    }
+    operands.iter().map(|operand| operand.trim().to_string()).collect()
+}
+
+// The contents of a "quoted" operand
+fn string_literal(text: &str) -> Option<&str> {
+    text.trim().strip_prefix('"')?.strip_suffix('"')
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn operand_kind(text: &str) -> Operand {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::Dt,
+        "ST" => return Operand::St,
+        "K" => return Operand::K,
+        "F" => return Operand::F,
+        "HF" => return Operand::Hf,
+        "B" => return Operand::B,
+        "R" => return Operand::R,
+        _ => {}
+    }
+    if let Some(register) = upper.strip_prefix('V').filter(|digit| digit.len() == 1) {
+        if let Ok(register) = u16::from_str_radix(register, 16) {
+            return Operand::V(register);
+        }
+    }
+    match split_word(text) {
+        (word, expr) if word.eq_ignore_ascii_case("LONG") => Operand::Long(expr.to_string()),
+        _ => Operand::Value(text.to_string()),
+    }
+}
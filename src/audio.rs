@@ -0,0 +1,177 @@
+use std::f32::consts::TAU;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use rodio::Source;
+use crate::cpu::CHIP8;
+
+// The sound of the sound timer. One tone generator plays for the whole
+// session and the frontend switches it on and off once per frame, rather
+// than starting a new clip every frame, so the tone is continuous while the
+// timer runs. Switching fades the tone in and out over a few milliseconds,
+// which keeps the waveform from jumping and clicking.
+//
+// Programs that load an XO-CHIP audio pattern hear that pattern instead, one
+// bit per sample at the pitch the program set, looping every 128 bits.
+
+// Technical References:
+// https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html#audio
+
+// The waveforms `Waveform::from_name` accepts
+pub const WAVEFORMS: [&str; 3] = ["square", "sine", "triangle"];
+
+// Samples per second the generator produces
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// Time taken to fade the tone in or out
+const FADE: Duration = Duration::from_millis(5);
+
+// Samples between looking for a new voice
+const UPDATE_SAMPLES: u32 = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    #[default]
+    Square,      // The buzzer most interpreters had
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    // The wave at `phase` through a cycle, from 0 up to 1, between -1 and 1
+    fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+// What the generator should be playing
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Voice {
+    pub on: bool,
+    pub frequency: f32,                     // Tone frequency in Hz
+    pub waveform: Waveform,
+    pub volume: f32,                        // Peak amplitude, from 0 to 1
+    pub pattern: Option<([u8; 16], f32)>,   // XO-CHIP audio pattern and its bit rate in Hz
+}
+
+impl Voice {
+    // The sound a machine makes at the end of a frame, silent unless it is
+    // running with the sound timer set
+    pub fn of(chip8: &CHIP8, running: bool) -> Voice {
+        let config = &chip8.config;
+        Voice {
+            on: running && chip8.sound_timer != 0 && !config.mute,
+            frequency: config.beep_frequency,
+            waveform: config.waveform,
+            volume: config.volume.min(100) as f32 / 100.0,
+            pattern: chip8.audio_pattern.map(|pattern| (pattern, chip8.pattern_rate())),
+        }
+    }
+}
+
+// Changes the voice of a Tone from another thread
+#[derive(Clone)]
+pub struct ToneControl {
+    voice: Arc<Mutex<Voice>>,
+}
+
+impl ToneControl {
+    pub fn set(&self, voice: Voice) {
+        if let Ok(mut current) = self.voice.lock() {
+            *current = voice;
+        }
+    }
+}
+
+// The tone generator, an endless rodio source of mono samples
+pub struct Tone {
+    control: ToneControl,
+    voice: Voice,
+    sample_rate: u32,
+    gain: f32,       // Current amplitude, moving towards the voice's volume
+    phase: f32,      // Position through a cycle of the tone, from 0 up to 1
+    bit: f32,        // Position through the audio pattern, from 0 up to 128
+    countdown: u32,  // Samples until the voice is looked at again
+}
+
+impl Tone {
+    pub fn new(sample_rate: u32) -> Tone {
+        Tone {
+            control: ToneControl { voice: Arc::new(Mutex::new(Voice::default())) },
+            voice: Voice::default(),
+            sample_rate: sample_rate.max(1),
+            gain: 0.0,
+            phase: 0.0,
+            bit: 0.0,
+            countdown: 0,
+        }
+    }
+
+    pub fn control(&self) -> ToneControl {
+        self.control.clone()
+    }
+}
+
+impl Iterator for Tone {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.countdown == 0 {
+            if let Ok(voice) = self.control.voice.lock() {
+                self.voice = *voice;
+            }
+            self.countdown = UPDATE_SAMPLES;
+        }
+        self.countdown -= 1;
+
+        let voice = self.voice;
+        let rate = self.sample_rate as f32;
+        let target = if voice.on { voice.volume.clamp(0.0, 1.0) } else { 0.0 };
+        let step = 1.0 / (FADE.as_secs_f32() * rate).max(1.0);
+        self.gain = if self.gain < target { (self.gain + step).min(target) } else { (self.gain - step).max(target) };
+
+        let wave = match voice.pattern {
+            Some((pattern, bit_rate)) => {
+                let bit = self.bit as usize;
+                self.bit = (self.bit + bit_rate / rate) % 128.0;
+                if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 }
+            }
+            None => {
+                let wave = voice.waveform.sample(self.phase);
+                self.phase = (self.phase + voice.frequency.max(0.0) / rate).fract();
+                wave
+            }
+        };
+        Some(wave * self.gain)
+    }
+}
+
+impl Source for Tone {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
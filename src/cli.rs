@@ -0,0 +1,439 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::audio::{Waveform, WAVEFORMS};
+use crate::filter::Filter;
+use crate::palette::{Palette, PALETTES};
+use crate::quirks::PRESETS;
+use crate::render::{ColorDepth, RenderMode};
+use crate::screenshot::ImageFormat;
+use crate::video::VideoFormat;
+
+// Command-line options for the emulator binary. Parsed by hand since there are
+// only a handful of them; every option accepts both `--name value` and
+// `--name=value`.
+
+pub const USAGE: &str = "\
+Usage: chip-8 [OPTIONS] <ROM>
+       chip-8 disasm [--platform <NAME>] <ROM>
+       chip-8 asm [-o <OUT>] <SOURCE>
+
+Runs a CHIP-8, SUPER-CHIP or XO-CHIP ROM in the terminal. Octo source files
+ending in .8o are compiled when loaded.
+
+Commands:
+  disasm               Print the ROM as assembly, decoding the instructions of
+                       the --platform given
+  asm                  Assemble source in the syntax disasm prints, or compile
+                       Octo source ending in .8o, into a ROM written to <OUT>
+                       or next to the source as .ch8
+
+Options:
+  --ips <N>            Instructions executed per second [default: 700]
+  --platform <NAME>    Platform and quirk preset: chip8, vip, chip48, schip1.0,
+                       schip1.1 or xochip [default: chip8]
+  --render <MODE>      How pixels are drawn: block for one per character cell,
+                       half for two stacked in each cell, or braille for 2x4
+                       [default: half]
+  --scale <N>          Size multiplier for CHIP-8 pixels, or auto for the
+                       largest that fits the window [default: auto]
+  --keymap <MAP>       Host keys for the hex keypad: qwerty, azerty, colemak or
+                       16 characters for keys 0-F in order [default: qwerty]
+  --input <BACKEND>    Keyboard backend: terminal, or device-query when built
+                       with the device-query feature [default: terminal]
+  --hold-timeout <MS>  How long a key stays held after the terminal last
+                       reported it, when key releases are unavailable
+                       [default: 250]
+  --tone <HZ>          Frequency of the sound timer tone [default: 440]
+  --waveform <NAME>    Shape of the tone: square, sine or triangle
+                       [default: square]
+  --volume <PERCENT>   Sound volume from 0 to 100 [default: 25]
+  --mute               Disable sound. Sound settings given here win over
+                       those stored in save states and movies
+  --seed <N>           Seed for the CXNN random number generator, making runs
+                       repeatable [default: random]
+  --debug              Start paused with the debugger panels open. F5 runs or
+                       pauses, F6 steps, F4 steps back, F7 steps over calls,
+                       F8 runs to the cursor, F9 toggles a breakpoint at the
+                       cursor, and the arrow, Page Up/Down and Home keys move
+                       the cursor
+  --break <ADDR>       Breakpoint at a hex address such as 0x2A4, may be given
+                       more than once; opens the debugger without pausing
+  --load-state <FILE>  Start from a save state instead of the beginning of the
+                       ROM. The state brings its own platform, quirks and
+                       speed. F2 quick-saves to the ROM path with a .state
+                       extension and F3 loads that file back
+  --record <FILE>      Record the keys pressed in every frame to a movie file,
+                       written when the session ends
+  --play <FILE>        Play a movie back, using its settings instead of the
+                       ones given here, and check the final display matches
+                       the recording. Works with --headless without --frames
+  --rewind <SECONDS>   History kept for rewinding with Backspace, one frame
+                       per press, or 0 to disable it [default: 10]
+  --headless           Run without the terminal UI and print the final display
+  --frames <N>         Number of 60Hz frames to run in headless mode
+  --screenshot <FILE>  Also write the final display in headless mode to an
+                       image, as .png, .ppm or .pbm by its extension. F12
+                       saves numbered PNGs next to the ROM
+  --video <FILE>       Record the display in every frame to a video, as .gif,
+                       .y4m or raw RGB .rgb by its extension. F11 stops it, or
+                       starts and stops numbered GIFs next to the ROM
+  --image-scale <N>    Image pixels per CHIP-8 pixel in screenshots and videos
+                       [default: 8]
+  --palette <COLOURS>  Display colours, also used for screenshots and videos:
+                       default, green, amber, mono, octo, gameboy, or two to
+                       four colours for off, plane 1, plane 2 and both planes,
+                       each hex RGB or an xterm 256 colour number, such as
+                       #000000,#33FF66. Without it, a file next to the ROM
+                       with a .palette extension holding the same is used
+                       [default: default]
+  --colors <DEPTH>     Colours the terminal can show: truecolor, 256 or 16
+                       [default: detected from COLORTERM and TERM]
+  --filter <NAME>      Display filter against sprite flicker: off, merge to
+                       show pixels lit in this frame or the last, or phosphor
+                       to fade pixels out, optionally with the percentage of
+                       brightness kept each frame as in phosphor:70. Without
+                       it, a .filter file next to the ROM is used
+                       [default: off]
+  -h, --help           Print this help
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom: PathBuf,
+    pub ips: i32,
+    pub platform: String,
+    pub render: RenderMode,
+    pub scale: Option<u16>,
+    pub keymap: [char; 16],
+    pub input: InputBackend,
+    pub hold_timeout: Duration,
+    pub tone: Option<f32>,
+    pub waveform: Option<Waveform>,
+    pub volume: Option<u8>,
+    pub mute: bool,
+    pub seed: Option<u64>,
+    pub debug: bool,
+    pub breakpoints: Vec<u16>,
+    pub load_state: Option<PathBuf>,
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    pub rewind: u32,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub screenshot: Option<PathBuf>,
+    pub video: Option<PathBuf>,
+    pub image_scale: usize,
+    pub palette: Option<Palette>,
+    pub colors: Option<ColorDepth>,
+    pub filter: Option<Filter>,
+}
+
+// Where keyboard input comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputBackend {
+    Terminal,      // Key events from the terminal through crossterm
+    DeviceQuery,   // Global keyboard polling through device_query
+}
+
+// What the command line asked us to do
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Run(Box<Options>),
+    Disasm { rom: PathBuf, platform: String },
+    Asm { source: PathBuf, output: Option<PathBuf> },
+    Help,
+}
+
+// Host keys for the hex keys 0x0-0xF, laid out so the 4x4 block under 1-4
+// matches the original keypad:
+//   1 2 3 C        1 2 3 4
+//   4 5 6 D   <-   Q W E R
+//   7 8 9 E        A S D F
+//   A 0 B F        Z X C V
+pub fn keymap(name: &str) -> Option<[char; 16]> {
+    let keys = match name.to_ascii_lowercase().as_str() {
+        "qwerty" => "x123qweasdzc4rfv",
+        "azerty" => "x123azeqsdwc4rfv",
+        "colemak" => "x123qwfarszc4ptv",
+        _ => name,
+    };
+    let keys: Vec<char> = keys.chars().map(|key| key.to_ascii_lowercase()).collect();
+    if keys.len() != 16 || !keys.iter().all(char::is_ascii_alphanumeric) {
+        return None;
+    }
+    let mut keymap = [' '; 16];
+    keymap.copy_from_slice(&keys);
+    Some(keymap)
+}
+
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("disasm") => {
+            args.next();
+            return parse_disasm(args);
+        }
+        Some("asm") => {
+            args.next();
+            return parse_asm(args);
+        }
+        _ => {}
+    }
+
+    let mut rom = None;
+    let mut ips = 700;
+    let mut platform = String::from("chip8");
+    let mut render = RenderMode::HalfBlock;
+    let mut scale = None;
+    let mut keys = keymap("qwerty").unwrap();
+    let mut input = InputBackend::Terminal;
+    let mut hold_timeout = Duration::from_millis(250);
+    let mut tone = None;
+    let mut waveform = None;
+    let mut volume = None;
+    let mut mute = false;
+    let mut seed = None;
+    let mut debug = false;
+    let mut breakpoints = Vec::new();
+    let mut load_state = None;
+    let mut record = None;
+    let mut play = None;
+    let mut rewind = 10;
+    let mut headless = false;
+    let mut frames = None;
+    let mut screenshot = None;
+    let mut video = None;
+    let mut image_scale = 8;
+    let mut palette = None;
+    let mut colors = None;
+    let mut filter = None;
+
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = split(&arg);
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).ok_or(format!("{} requires a value", name))
+        };
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--ips" => {
+                ips = number(&name, &value()?)?;
+                if ips <= 0 {
+                    return Err(String::from("--ips must be at least 1"));
+                }
+            }
+            "--platform" => platform = preset(value()?)?,
+            "--render" => {
+                let name = value()?;
+                render = RenderMode::from_name(&name).ok_or(format!("unknown render mode '{}'", name))?;
+            }
+            "--scale" => {
+                scale = match value()?.as_str() {
+                    "auto" => None,
+                    value => Some(number(&name, value)?),
+                };
+                if scale == Some(0) {
+                    return Err(String::from("--scale must be at least 1"));
+                }
+            }
+            "--keymap" => {
+                let name = value()?;
+                keys = keymap(&name).ok_or(format!("invalid keymap '{}'", name))?;
+            }
+            "--input" => {
+                input = match value()?.as_str() {
+                    "terminal" => InputBackend::Terminal,
+                    "device-query" if cfg!(feature = "device-query") => InputBackend::DeviceQuery,
+                    "device-query" => return Err(String::from("built without the device-query feature")),
+                    other => return Err(format!("unknown input backend '{}'", other)),
+                };
+            }
+            "--hold-timeout" => hold_timeout = Duration::from_millis(number(&name, &value()?)?),
+            "--tone" => {
+                let hz: f32 = number(&name, &value()?)?;
+                if !(hz > 0.0 && hz <= 20_000.0) {
+                    return Err(String::from("--tone must be between 0 and 20000 Hz"));
+                }
+                tone = Some(hz);
+            }
+            "--waveform" => {
+                let name = value()?;
+                waveform = Some(Waveform::from_name(&name).ok_or(format!(
+                    "unknown waveform '{}', expected one of {}",
+                    name,
+                    WAVEFORMS.join(", ")
+                ))?);
+            }
+            "--volume" => {
+                let percent = number(&name, &value()?)?;
+                if percent > 100 {
+                    return Err(String::from("--volume must be between 0 and 100"));
+                }
+                volume = Some(percent);
+            }
+            "--mute" => mute = true,
+            "--seed" => seed = Some(number(&name, &value()?)?),
+            "--debug" => debug = true,
+            "--break" => {
+                let addr = value()?;
+                let digits = addr.trim_start_matches("0x").trim_start_matches("0X");
+                breakpoints.push(u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}' for --break", addr))?);
+            }
+            "--load-state" => load_state = Some(PathBuf::from(value()?)),
+            "--record" => record = Some(PathBuf::from(value()?)),
+            "--play" => play = Some(PathBuf::from(value()?)),
+            "--rewind" => rewind = number(&name, &value()?)?,
+            "--headless" => headless = true,
+            "--frames" => frames = Some(number(&name, &value()?)?),
+            "--screenshot" => {
+                let path = PathBuf::from(value()?);
+                if ImageFormat::from_path(&path).is_none() {
+                    return Err(format!("--screenshot file '{}' must end in .png, .ppm or .pbm", path.display()));
+                }
+                screenshot = Some(path);
+            }
+            "--video" => {
+                let path = PathBuf::from(value()?);
+                if VideoFormat::from_path(&path).is_none() {
+                    return Err(format!("--video file '{}' must end in .gif, .y4m or .rgb", path.display()));
+                }
+                video = Some(path);
+            }
+            "--image-scale" => {
+                image_scale = number(&name, &value()?)?;
+                if image_scale == 0 {
+                    return Err(String::from("--image-scale must be at least 1"));
+                }
+            }
+            "--palette" => {
+                let name = value()?;
+                palette = Some(Palette::parse(&name).ok_or(format!(
+                    "invalid palette '{}', expected one of {} or a list of colours",
+                    name,
+                    PALETTES.join(", ")
+                ))?);
+            }
+            "--colors" => {
+                let name = value()?;
+                colors = Some(ColorDepth::from_name(&name).ok_or(format!("unknown colour depth '{}'", name))?);
+            }
+            "--filter" => {
+                let name = value()?;
+                filter = Some(Filter::parse(&name).ok_or(format!("unknown display filter '{}'", name))?);
+            }
+            _ if name.starts_with('-') => return Err(format!("unknown option '{}'", name)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let rom = rom.ok_or("no ROM file given")?;
+    if headless && frames.is_none() && play.is_none() {
+        return Err(String::from("--headless requires --frames"));
+    }
+    if screenshot.is_some() && !headless {
+        return Err(String::from("--screenshot needs --headless, use F12 in the terminal UI"));
+    }
+    // Movies start from a freshly loaded ROM and run one frame per poll
+    if record.is_some() || play.is_some() {
+        if record.is_some() && play.is_some() {
+            return Err(String::from("--record and --play cannot be used together"));
+        }
+        if record.is_some() && headless {
+            return Err(String::from("--record needs the terminal UI"));
+        }
+        if load_state.is_some() || debug || !breakpoints.is_empty() {
+            return Err(String::from("movies cannot be combined with --load-state, --debug or --break"));
+        }
+    }
+    Ok(Command::Run(Box::new(Options {
+        rom,
+        ips,
+        platform,
+        render,
+        scale,
+        keymap: keys,
+        input,
+        hold_timeout,
+        tone,
+        waveform,
+        volume,
+        mute,
+        seed,
+        debug,
+        breakpoints,
+        load_state,
+        record,
+        play,
+        rewind,
+        headless,
+        frames,
+        screenshot,
+        video,
+        image_scale,
+        palette,
+        colors,
+        filter,
+    })))
+}
+
+// The options for `chip-8 disasm`
+fn parse_disasm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut rom = None;
+    let mut platform = String::from("chip8");
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = split(&arg);
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).ok_or(format!("{} requires a value", name))
+        };
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--platform" => platform = preset(value()?)?,
+            _ if name.starts_with('-') => return Err(format!("unknown option '{}'", name)),
+            _ if rom.is_none() => rom = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom = rom.ok_or("no ROM file given")?;
+    Ok(Command::Disasm { rom, platform })
+}
+
+// The options for `chip-8 asm`
+fn parse_asm<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut source = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = split(&arg);
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).ok_or(format!("{} requires a value", name))
+        };
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            _ if name.starts_with('-') => return Err(format!("unknown option '{}'", name)),
+            _ if source.is_none() => source = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let source = source.ok_or("no source file given")?;
+    Ok(Command::Asm { source, output })
+}
+
+// Splits `--name=value` into its name and value
+fn split(arg: &str) -> (String, Option<String>) {
+    match arg.split_once('=') {
+        Some((name, value)) if name.starts_with("--") => (name.to_string(), Some(value.to_string())),
+        _ => (arg.to_string(), None),
+    }
+}
+
+// Checks a --platform value against the preset names
+fn preset(platform: String) -> Result<String, String> {
+    if !PRESETS.contains(&platform.to_ascii_lowercase().as_str()) {
+        return Err(format!("unknown platform '{}', expected one of {}", platform, PRESETS.join(", ")));
+    }
+    Ok(platform)
+}
+
+fn number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value '{}' for {}", value, name))
+}
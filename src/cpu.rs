@@ -1,13 +1,11 @@
-#![allow(dead_code)]
-use std::sync::{Mutex, Arc};
-use std::time::Duration;
-use crate::render::ChipRender;
-use device_query::Keycode;
-use device_query::{DeviceQuery, DeviceState, Keycode::*};
-use rand::Rng;
-use rodio::source::SineWave;
-use spin_sleep;
-use rodio::{self, Source, OutputStream};
+use std::path::Path;
+use crate::audio::Waveform;
+use crate::disasm;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::quirks::{IndexIncrement, Platform, Quirks};
+use crate::random::Random;
+use crate::snapshot::Snapshot;
 
 // CHIP-8 Emulator written in the RUST programming language. 🦀 Praise Be Ferris 🦀
 // by Jason Zingaretti
@@ -16,73 +14,177 @@ use rodio::{self, Source, OutputStream};
 // http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#memmap
 // https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#fetch
 
-pub fn start_clock(chip8:&Arc<Mutex<CHIP8>>) -> (){
-    let chip8 = Arc::clone(chip8);
-    std::thread::spawn( move ||{
-        // Playing audio using sound timer
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let source = SineWave::new(43.648).take_duration(Duration::from_secs_f32(1.0));
-            loop {
-            spin_sleep::sleep(Duration::from_secs_f32(1.0/60.0));
-            let mut chip8 = chip8.lock().unwrap();
-            if chip8.sound_timer != 0{
-                stream_handle.play_raw(source.clone()).unwrap();      
-            }
-            chip8.delay_timer = chip8.delay_timer.saturating_sub(1);
-            chip8.sound_timer = chip8.sound_timer.saturating_sub(1);
-            log::error!("timer: {}",chip8.delay_timer);
-            drop(chip8);
-        }
-
-    });
-}
+// The emulation core. Nothing in here knows about the terminal, audio or the
+// host keyboard: a frontend feeds key states into `keypad`, calls `step`,
+// `run_cycles` or `run_frame` and reads `display` back out, which lets the
+// CPU run headless.
 
+// Address programs are loaded at and start executing from
+pub const PROGRAM_START: u16 = 0x200;
+// Maximum depth of nested 2NNN subroutine calls
+pub const STACK_SIZE: usize = 16;
+// Addresses of the 4x5 hex font and the 8x10 SUPER-CHIP font
+pub const FONT_START: u16 = 0x050;
+pub const BIG_FONT_START: u16 = 0x0A0;
 
 // CHIP-8 SPECIFICATION DETAILS
 pub struct CHIP8 {
-    pub memory: [u8; 4096],            // 4KB ~ 4,096 Bytes RAM (0x00-0x200 reserved)
+    pub memory: Vec<u8>,               // 4KB ~ 4,096 Bytes RAM, 64KB on XO-CHIP (0x00-0x200 reserved)
     pub pc: u16,                       // Program Counter (16-bit)
     pub index: u16,                    // Index Register  (16-bit)
     pub stack: Vec<u16>,               // Stack of (16-bit) addresses
     pub delay_timer: u8,               // Delay Timer 8-bit @ 60Hz
     pub sound_timer: u8,               // Sound Timer 8-bit @ 60Hz
     pub variables: [u8; 16],           // General purpose variable registers (0x0-0xF)
-    pub display: [[bool; 32]; 64],     // Display output of 64 by 32 pixels
+    pub display: Display,              // Display output of 64 by 32 (or 128 by 64) pixels
+    pub display_dirty: bool,           // Whether the display changed since a frontend last drew it
+    pub keypad: [bool; 16],            // Pressed state of the hex keys 0x0-0xF
+    pub rpl: [u8; 16],                 // SUPER-CHIP RPL user flags saved by FX75
+    pub vblank: bool,                  // Whether a 60Hz frame boundary passed since the last draw
+    pub plane: u8,                     // XO-CHIP bit-planes selected for drawing (FN01)
+    pub audio_pattern: Option<[u8; 16]>, // XO-CHIP 128-bit audio pattern loaded by F002
+    pub pitch: u8,                     // XO-CHIP playback pitch of the audio pattern (FX3A)
+    rng: Random,                       // Random number source for CXNN
 
-    pub renderer: ChipRender, // Renderer struct for the chip
     pub config: Config,       // Configurable chip-8 settings
 }
 
+// The outcome of executing a single instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub pc: u16,                 // Address the instruction was fetched from
+    pub opcode: u16,             // The raw 16-bit instruction that was executed
+    pub display_changed: bool,   // Whether the instruction modified the display
+    pub waiting_for_key: bool,   // Whether FX0A is blocked waiting on a key press
+    pub waiting_for_vblank: bool, // Whether DXYN is blocked waiting for the next frame (display wait quirk)
+    pub exited: bool,            // Whether the program executed the SUPER-CHIP exit instruction
+}
+
+// The combined outcome of executing several instructions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Run {
+    pub cycles: u32,             // Number of instructions executed
+    pub display_changed: bool,   // Whether any instruction modified the display
+    pub waiting_for_key: bool,   // Whether execution stopped on FX0A waiting for a key
+    pub waiting_for_vblank: bool, // Whether execution stopped on DXYN waiting for the next frame
+    pub exited: bool,            // Whether execution stopped on the SUPER-CHIP exit instruction
+    pub last: Option<Step>,      // The last instruction executed, if any
+}
+
+impl Run {
+    // Folds the outcome of one more instruction into the total
+    pub(crate) fn record(&mut self, step: Step) {
+        self.cycles += 1;
+        self.display_changed |= step.display_changed;
+        self.waiting_for_key = step.waiting_for_key;
+        self.waiting_for_vblank = step.waiting_for_vblank;
+        self.exited = step.exited;
+        self.last = Some(step);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Config {
-    ips: i32,
-    beep_frequency: f32,
+    pub ips: i32,             // Instructions executed per second
+    pub beep_frequency: f32,  // Frequency of the sound timer tone in Hz
+    pub waveform: Waveform,   // Shape of the sound timer tone
+    pub volume: u8,           // Sound volume in percent
+    pub mute: bool,           // Whether to stay silent
+    pub platform: Platform,   // Instruction set extensions to decode
+    pub quirks: Quirks,       // Behaviour of the ambiguous instructions
+    pub seed: Option<u64>,    // Seed for CXNN random numbers, random when unset
+}
+
+impl Config {
+    // Number of instructions executed during one 60Hz frame
+    pub fn cycles_per_frame(&self) -> u32 {
+        (self.ips.max(0) as u32).div_ceil(60)
+    }
+
+    // Default settings with the platform and quirks of one of the named
+    // presets in `quirks::PRESETS`
+    pub fn preset(name: &str) -> Option<Config> {
+        Some(Config {
+            platform: Platform::preset(name)?,
+            quirks: Quirks::preset(name)?,
+            ..Config::default()
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            ips: 700,
+            beep_frequency: 440.0,
+            waveform: Waveform::default(),
+            volume: 25,
+            mute: false,
+            platform: Platform::default(),
+            quirks: Quirks::default(),
+            seed: None,
+        }
+    }
+}
+
+impl Default for CHIP8 {
+    fn default() -> CHIP8 {
+        CHIP8::new()
+    }
 }
 
 impl CHIP8 {
+    // Makes sure the `len` bytes starting at `access` lie inside memory,
+    // blaming the instruction at `addr` otherwise
+    fn check_range(&self, addr: u16, access: usize, len: usize) -> Result<(), Chip8Error> {
+        if access + len > self.memory.len() {
+            return Err(Chip8Error::MemoryOutOfRange { addr, access: access + len - 1 });
+        }
+        Ok(())
+    }
+
     // Read from memory
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    fn mem_read(&self, addr: u16, access: usize) -> Result<u8, Chip8Error> {
+        self.check_range(addr, access, 1)?;
+        Ok(self.memory[access])
     }
 
     // Write to memory
-    fn mem_write(&mut self, addr: u16, data: u8) -> () {
-        self.memory[addr as usize] = data
+    fn mem_write(&mut self, addr: u16, access: usize, data: u8) -> Result<(), Chip8Error> {
+        self.check_range(addr, access, 1)?;
+        self.memory[access] = data;
+        Ok(())
     }
 
-    pub fn load_program(&mut self, path: String) -> () {
+    pub fn load_program<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Chip8Error> {
         // Taking in a vector of bytes from a file
-        let program = std::fs::read(path).unwrap();
+        let program = std::fs::read(path)?;
+        self.load_rom(&program)
+    }
+
+    pub fn load_rom(&mut self, program: &[u8]) -> Result<(), Chip8Error> {
+        let start = PROGRAM_START as usize;
+        let max = self.memory.len() - start;
+        if program.len() > max {
+            return Err(Chip8Error::RomTooLarge { size: program.len(), max });
+        }
         // Copying the program data into memory starting from Byte 512 (0x200)
-        self.memory[0x200..(0x200 + program.len())].copy_from_slice(&program[..]);
-        self.pc = 0x200; // Initializing program counter
+        self.memory[start..(start + program.len())].copy_from_slice(program);
+        self.pc = PROGRAM_START; // Initializing program counter
+        Ok(())
     }
 
     // Initializing CHIP-8
-    pub fn new() -> Arc<Mutex<CHIP8>> {
-        let mut memory: [u8; 4096] = [0x00; 4096];
+    pub fn new() -> CHIP8 {
+        CHIP8::with_config(Config::default())
+    }
+
+    // Initializing CHIP-8 with custom settings
+    pub fn with_config(config: Config) -> CHIP8 {
+        let mut memory = vec![0x00; config.platform.memory_size()];
 
         // The address space [0x000 to 0x200) is reserved for the interpreter,
-        // while [0x200 to 0xFFF] is free RAM for the programs to use.
+        // while [0x200 to 0xFFF] (0xFFFF on XO-CHIP) is free RAM for the programs to use.
 
         let font_data: [u8; 80] = [
             0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -106,51 +208,96 @@ impl CHIP8 {
         // Loading font data in the standard range of 0x050-0x09F
         memory[0x050..=0x09F].copy_from_slice(&font_data);
 
-        Arc::new(Mutex::new(CHIP8 {
-            memory: memory,
+        // The SUPER-CHIP big font, 10 bytes per digit (A-F as drawn by Octo)
+        let big_font_data: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
+        // Loading the big font right after it in 0x0A0-0x13F
+        memory[0x0A0..=0x13F].copy_from_slice(&big_font_data);
+
+        CHIP8 {
+            memory,
             pc: 0x00,
             index: 0x00,
             stack: Vec::new(),
             delay_timer: 0,
             sound_timer: 0,
             variables: [0; 16],
-            display: [[false; 32]; 64],
-            renderer: ChipRender::setup().expect("Failed to initialize chip display renderer"),
-            config: Config { ips: 700, beep_frequency: 34.648 },
-        }))
+            display: Display::default(),
+            display_dirty: true,
+            keypad: [false; 16],
+            rpl: [0; 16],
+            vblank: true,
+            plane: 1,
+            audio_pattern: None,
+            pitch: 64,
+            rng: match config.seed {
+                Some(seed) => Random::new(seed),
+                None => Random::from_entropy(),
+            },
+            config,
+        }
+    }
 
+    // Executes instructions until `cycles` have run or the program blocks on a
+    // key press or the next frame
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<Run, Chip8Error> {
+        let mut run = Run::default();
+        for _ in 0..cycles {
+            run.record(self.step()?);
+            if run.waiting_for_key || run.waiting_for_vblank || run.exited {
+                break;
+            }
+        }
+        Ok(run)
     }
 
-}
+    // Executes one 60Hz frame worth of instructions and then ticks the timers
+    pub fn run_frame(&mut self) -> Result<Run, Chip8Error> {
+        let run = self.run_cycles(self.config.cycles_per_frame())?;
+        self.tick_timers();
+        Ok(run)
+    }
 
-pub fn run(chip8:&Arc<Mutex<CHIP8>>) -> () {
-
-    // Setting up keyboard interface mappings of key inputs to hex values 0x0-0xF
-    let keys: [Keycode; 16] =  
-    [ X, Key1, Key2, Key3, Q, W, E, A, S, D, Z, C, Key4, R, F, V ];
-    let device_state = DeviceState::new();
-    
-    loop {
-        let mut chip8 = chip8.lock().unwrap();
-        let simulated_execution_time = 1.0/(chip8.config.ips as f32);
-        // Calculating desired time to sleep between instructions using desired IPS
-        // Sleeping the calculated time
-        spin_sleep::sleep(Duration::from_secs_f32(simulated_execution_time));
-        
-        // Update the vector of Keycodes corresponding to keys currently being pressed
-        let pressed_keys: Vec<Keycode> = device_state.get_keys();
-        if pressed_keys.contains(&Keycode::Escape){
-            panic!("Escape key pressed, program exited.");
+    // Fetches, decodes and executes a single instruction. On error the program
+    // counter is left pointing at the offending instruction.
+    pub fn step(&mut self) -> Result<Step, Chip8Error> {
+        let start_pc = self.pc;
+        let result = self.execute();
+        match &result {
+            Ok(step) => self.display_dirty |= step.display_changed,
+            Err(_) => self.pc = start_pc,
         }
+        result
+    }
+
+    fn execute(&mut self) -> Result<Step, Chip8Error> {
         // FETCH STAGE
         // Fetch the instruction from the program counter
-        let inst_part1 = chip8.mem_read(chip8.pc);
-        let inst_part2 = chip8.mem_read(chip8.pc + 1);
+        let start_pc = self.pc;
+        let inst_part1 = self.mem_read(start_pc, self.pc as usize)?;
+        let inst_part2 = self.mem_read(start_pc, self.pc as usize + 1)?;
         // Combining both parts of the instruction to make the true instruction
         // using bit-wise or and padding with zeros.
         let instruction: u16 = ((inst_part1 as u16) << 8) | inst_part2 as u16;
         // Increment the program counter by 2
-        chip8.pc = chip8.pc + 2;
+        self.pc = self.pc.wrapping_add(2);
 
         // DECODE STAGE
         // Extracting information from the instruction half-bytes (nibbles or nybbles, lol)
@@ -164,141 +311,184 @@ pub fn run(chip8:&Arc<Mutex<CHIP8>>) -> () {
         // EXECUTE STAGE
         // This match statement contains all the instruction logic that can be executed by the CHIP-8,
         // implemented according to their original corresponding functionality.
-        log::info!("op:{} x:{} y:{} n:{} byte:{} addr:{}",op,x,y,n,byte,addr);
-        let display = chip8.display.clone();
-        chip8.renderer.render(& mut display.clone());
+        log::info!("{:04X}: {}", start_pc, disasm::decode(&self.memory, start_pc, self.config.platform).0);
+        let mut step = Step {
+            pc: start_pc,
+            opcode: instruction,
+            display_changed: false,
+            waiting_for_key: false,
+            waiting_for_vblank: false,
+            exited: false,
+        };
+        let quirks = self.config.quirks;
+        let platform = self.config.platform;
+        let extended = platform != Platform::Chip8;
+        let xo_chip = platform == Platform::XoChip;
+        // Drawing, clearing and scrolling only touch the selected XO-CHIP planes
+        let planes = if xo_chip { self.plane } else { 1 };
+        let unknown = Chip8Error::UnknownOpcode { addr: start_pc, opcode: instruction };
         match op {
-            
+
             0 => {
-                match n{
+                match instruction {
                     // Clear screen (00E0)
-                    0 => {
-                        chip8.display = [[false; 32]; 64];
-                        
-                      
+                    0x00E0 => {
+                        self.display.clear(planes);
+                        step.display_changed = true;
                     }
                     // 00EE subroutine
-                    _=>{
-                        chip8.pc = chip8.stack[chip8.stack.len()-1];
-                        chip8.stack.pop();
-                      
-                        
+                    0x00EE => {
+                        self.pc = self.stack.pop().ok_or(Chip8Error::StackUnderflow { addr: start_pc })?;
+                    }
+                    // SUPER-CHIP 00CN Scroll the display down N pixels
+                    0x00C0..=0x00CF if extended => {
+                        self.display.scroll_down(n as usize, planes);
+                        step.display_changed = true;
+                    }
+                    // XO-CHIP 00DN Scroll the display up N pixels
+                    0x00D0..=0x00DF if xo_chip => {
+                        self.display.scroll_up(n as usize, planes);
+                        step.display_changed = true;
+                    }
+                    // SUPER-CHIP 00FB Scroll the display right 4 pixels
+                    0x00FB if extended => {
+                        self.display.scroll_right(4, planes);
+                        step.display_changed = true;
+                    }
+                    // SUPER-CHIP 00FC Scroll the display left 4 pixels
+                    0x00FC if extended => {
+                        self.display.scroll_left(4, planes);
+                        step.display_changed = true;
+                    }
+                    // SUPER-CHIP 00FD Exit the interpreter, staying on this instruction
+                    0x00FD if extended => {
+                        self.pc = start_pc;
+                        step.exited = true;
+                    }
+                    // SUPER-CHIP 00FE/00FF Switch to low/high resolution mode
+                    0x00FE | 0x00FF if extended => {
+                        self.display.set_hires(instruction == 0x00FF);
+                        step.display_changed = true;
+                    }
+                    _ => {
+                        return Err(unknown);
                     }
                 }
             }
 
             // Jump (1NNN)
             1 => {
-                chip8.pc = addr;
-                
+                self.pc = addr;
             }
-            // Set register vx (6XNN)
             //2NNN Subroutine
             2 =>{
-                let stack_pc = chip8.pc.clone();
-                chip8.stack.push(stack_pc);
-                chip8.pc = addr;
-               
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow { addr: start_pc });
+                }
+                self.stack.push(self.pc);
+                self.pc = addr;
             }
-
+            // Set register vx (6XNN)
             6 => {
-                chip8.variables[x as usize] = byte;
-                
+                self.variables[x as usize] = byte;
             }
             // Add value to register vx (7XNN)
             7 => {
-                chip8.variables[x as usize] = chip8.variables[x as usize].wrapping_add(byte);
-               
+                self.variables[x as usize] = self.variables[x as usize].wrapping_add(byte);
             }
             // Set index register I (ANNN)
             0xA => {
-                chip8.index = addr;
-               
+                self.index = addr;
             }
             // Draw to screen  (DXYN)
             0xD => {
-                // Getting the co-ordinates to be drawn to from registers vx and vy
-                let x_pos = ((chip8.variables[x as usize]) % 64) as usize; 
-                let y_pos = ((chip8.variables[y as usize]) % 32) as usize; 
-                //x_pos = ((x_pos % 64)+ 64) % 64 ; // x mod 64
-                //y_pos = ((y_pos % 32)+ 32) % 32 ; // y mod 32
-                let mut current_y_pos = y_pos;
-               
-                // Setting flag register to zero.
-                chip8.variables[15] = 0x0;
-                // For "n" rows on the screen
-                for row in 0..n {
-                    let mut current_x_pos = x_pos;
-                    // Getting a row of sprite data from the address stored in Index (I)
-                    // This is the n'th sprite data byte corresponding to the n'th row
-                    let row_of_sprite_data = chip8.memory[chip8.index as usize + row as usize];
-                    // Iterating over the bits in the chosen sprite byte as boolean value "bit"
-                    for bit in (0..8).rev().map(|i| (row_of_sprite_data >> i) & 1 == 1) {
-                        // If the bit on screen is on and the pixel of the sprite is on, turn
-                        // off the pixel and set the flag register (vf) to 1.
-                        if bit == true && chip8.display[current_x_pos][current_y_pos] == true {
-                            chip8.display[current_x_pos][current_y_pos] = false; // Turning off the pixel
-                            chip8.variables[15] = 1; // Setting vf to 1
-                        }
-                        // Otherwise if the bit on the screen is off and the pixel sprite is on,
-                        // turn on the pixel and set the flag register (vf) to 1.
-                        else if bit == true && chip8.display[current_x_pos][current_y_pos] == false{
-                            // Turning on the pixel
-                            chip8.display[current_x_pos][current_y_pos] = true;  
-                        }
-                        // If we reach the right edge of the screen, stop drawing the current row
-                        if current_x_pos == 63 {
-                            log::info!("Broke @ x = {}.",current_x_pos);
-                        
-                            break;
-                        }
-                        // Otherwise we continue and increment the x position of the current pixel on the display
-                        current_x_pos = current_x_pos + 1;
-                        
-                    }
-                    // Stop if we reached the bottom of the screen
-                    if current_y_pos == 31 {
-                        log::info!("Broke @ y = {}.",current_y_pos);
-                        
-                        break;
+                // With the display wait quirk only one sprite is drawn per frame,
+                // so repeat this instruction until the next frame starts.
+                if quirks.display_wait {
+                    if !self.vblank {
+                        self.pc = start_pc;
+                        step.waiting_for_vblank = true;
+                        return Ok(step);
                     }
-                    log::info!("Drew line @ y = {}.",current_y_pos);
-                    current_y_pos = current_y_pos + 1;
-                    
+                    self.vblank = false;
+                }
+                // DXY0 draws a 16x16 sprite (two bytes per row) on SUPER-CHIP,
+                // everything else draws an 8 pixel wide sprite of N rows.
+                let (sprite_width, sprite_height) =
+                    if n == 0 && platform != Platform::Chip8 { (16, 16) } else { (8, n as usize) };
+                let sprite_size = sprite_height * sprite_width / 8;
+                // Getting the co-ordinates to be drawn to from registers vx and vy
+                let x_pos = self.variables[x as usize] as usize % self.display.width();
+                let y_pos = self.variables[y as usize] as usize % self.display.height();
+
+                // On XO-CHIP each selected plane takes its own copy of the sprite data,
+                // stored one after the other starting at I.
+                let selected: Vec<u8> = [1, 2].into_iter().filter(|plane| planes & plane != 0).collect();
+                self.check_range(start_pc, self.index as usize, sprite_size * selected.len())?;
+
+                // VF is 1 if any pixel was turned off, except that SUPER-CHIP
+                // 1.1 counts the rows that did so or fell off the bottom in hires mode
+                let count_rows = quirks.vf_counts_rows && self.display.is_hires();
+                let mut flag = 0;
+                step.display_changed = true;
+                for (i, plane) in selected.into_iter().enumerate() {
+                    let sprite = self.index as usize + i * sprite_size;
+                    let (collided, clipped) = self.draw_sprite(sprite, x_pos, y_pos, sprite_width, sprite_height, plane);
+                    flag = if count_rows { flag + collided + clipped } else { flag.max(collided.min(1)) };
                 }
-                // Render the sprite on screen
+                self.variables[15] = flag as u8;
                 log::info!("Exiting from draw instruction succesfully.");
-                
-                log::info!("Exiting from rendering display succesfully.");
-                
             }
 
             // Skip Instuctions
             // 3XNN - Skip one instruction if the value in Vx is equal to NN
             3 => {
-                if chip8.variables[x as usize] == byte {
-                    chip8.pc = chip8.pc + 2;
-                   
+                if self.variables[x as usize] == byte {
+                    self.skip();
                 }
             }
             // 4XNN - Skip one instruction if the value in Vx is NOT equal to NN
             4 => {
-                if chip8.variables[x as usize] != byte {
-                    chip8.pc = chip8.pc + 2;
-                    
+                if self.variables[x as usize] != byte {
+                    self.skip();
                 }
             }
-            // 5XY0 - Skip one instruction if Vx and Vy are equal.
             5 => {
-                if chip8.variables[x as usize] == chip8.variables[y as usize] {
-                    chip8.pc = chip8.pc + 2;
-                   
+                match n {
+                    // XO-CHIP 5XY2 - Save Vx through Vy (in either order) to memory at I
+                    2 if xo_chip => {
+                        let registers = register_range(x, y);
+                        self.check_range(start_pc, self.index as usize, registers.len())?;
+                        for (offset, register) in registers.into_iter().enumerate() {
+                            self.memory[self.index as usize + offset] = self.variables[register];
+                        }
+                    }
+                    // XO-CHIP 5XY3 - Load Vx through Vy (in either order) from memory at I
+                    3 if xo_chip => {
+                        let registers = register_range(x, y);
+                        self.check_range(start_pc, self.index as usize, registers.len())?;
+                        for (offset, register) in registers.into_iter().enumerate() {
+                            self.variables[register] = self.memory[self.index as usize + offset];
+                        }
+                    }
+                    // 5XY0 - Skip one instruction if Vx and Vy are equal.
+                    0 => {
+                        if self.variables[x as usize] == self.variables[y as usize] {
+                            self.skip();
+                        }
+                    }
+                    _ => {
+                        return Err(unknown);
+                    }
                 }
             }
             // 9XY0 - Skip one instruction if Vx and Vy are NOT equal.
             9 => {
-                if chip8.variables[x as usize] != chip8.variables[y as usize] {
-                    chip8.pc = chip8.pc + 2;
+                if n != 0 {
+                    return Err(unknown);
+                }
+                if self.variables[x as usize] != self.variables[y as usize] {
+                    self.skip();
                 }
             }
 
@@ -307,224 +497,383 @@ pub fn run(chip8:&Arc<Mutex<CHIP8>>) -> () {
                 match n {
                     // 8X70 Set - Vx is set to the value in Vy
                     0 => {
-                        chip8.variables[x as usize] = chip8.variables[y as usize];
-                        
+                        self.variables[x as usize] = self.variables[y as usize];
                     }
                     // 8X71 Binary OR - Vx = Vx OR Vy
                     1 => {
-                        chip8.variables[x as usize] =
-                        chip8.variables[x as usize] | chip8.variables[y as usize];
+                        self.variables[x as usize] |= self.variables[y as usize];
+                        if quirks.vf_reset {
+                            self.variables[15] = 0;
+                        }
                     }
                     // 8XY2 Binary AND - Vx = Vx AND Vy
                     2 => {
-                        chip8.variables[x as usize] =
-                        chip8.variables[x as usize] & chip8.variables[y as usize];
+                        self.variables[x as usize] &= self.variables[y as usize];
+                        if quirks.vf_reset {
+                            self.variables[15] = 0;
+                        }
                     }
                     // 8XY3 Logical XOR - Vx = Vx XOR Vy
                     3 => {
-                        chip8.variables[x as usize] =
-                        chip8.variables[x as usize] ^ chip8.variables[y as usize];
+                        self.variables[x as usize] ^= self.variables[y as usize];
+                        if quirks.vf_reset {
+                            self.variables[15] = 0;
+                        }
                     }
                     // 8XY4 Add - Vx = Vx + Vy (with overflow flag)
+                    // The flag is written after the result for this and the
+                    // other flag-setting instructions below, so with VF as Vx
+                    // it ends up holding the flag, as on the original hardware.
                     4 => {
-                        // If the addition would result in an overflow
-                        if chip8.variables[x as usize] as u16 + chip8.variables[y as usize] as u16 > 255
-                        {
-                            // Set flag register to 1.
-                            chip8.variables[15] = 1;
-                        }
-                        chip8.variables[x as usize] =
-                        chip8.variables[x as usize].wrapping_add(chip8.variables[y as usize]);
+                        // The flag is 1 if the addition overflows, otherwise zero.
+                        let (sum, overflow) = self.variables[x as usize].overflowing_add(self.variables[y as usize]);
+                        self.variables[x as usize] = sum;
+                        self.variables[15] = overflow as u8;
                     }
                     // 8XY5 Subtract - Vx = Vx - Vy
                     5 => {
-                        // Set flag to 1
-                        chip8.variables[15] = 1;
-                        // If the result will underflow, set flag to zero.
-                        if chip8.variables[x as usize] < chip8.variables[y as usize] {
-                            chip8.variables[15] = 0;
-                        }
-                        chip8.variables[x as usize] = chip8.variables[x as usize].wrapping_sub(chip8.variables[y as usize]);
+                        // The flag is 1, or zero if the result will underflow.
+                        let flag = (self.variables[x as usize] >= self.variables[y as usize]) as u8;
+                        self.variables[x as usize] = self.variables[x as usize].wrapping_sub(self.variables[y as usize]);
+                        self.variables[15] = flag;
                     }
                     // 8XY6 Shift Right (Ambiguous Instruction)
                     6 => {
                         // (Optional Step) Set Vx to Vy
-                        //self.variables[x as usize] = self.variables[y as usize];
+                        if quirks.shift_uses_vy {
+                            self.variables[x as usize] = self.variables[y as usize];
+                        }
                         // Set flag bit to the bit that will get shifted out
-                        chip8.variables[15] = chip8.variables[x as usize] & 0x01;
+                        let flag = self.variables[x as usize] & 0x01;
                         // Shift Vx one to the right
-                        chip8.variables[x as usize] = chip8.variables[x as usize] >> 1;
+                        self.variables[x as usize] >>= 1;
+                        self.variables[15] = flag;
                     }
                     // 8XY7 Subtract - Vx = Vy - Vx
                     7 => {
-                        // Set flag to 1
-                        chip8.variables[15] = 1;
-                        // If the result will underflow, set flag to zero.
-                        if chip8.variables[y as usize] < chip8.variables[x as usize] {
-                            chip8.variables[15] = 0;
-                        }
-                        chip8.variables[x as usize] =
-                        chip8.variables[y as usize].overflowing_sub(chip8.variables[x as usize]).0;
+                        // The flag is 1, or zero if the result will underflow.
+                        let flag = (self.variables[y as usize] >= self.variables[x as usize]) as u8;
+                        self.variables[x as usize] =
+                        self.variables[y as usize].overflowing_sub(self.variables[x as usize]).0;
+                        self.variables[15] = flag;
                     }
                     // 8XYE Shift Left (Ambiguous Instruction)
                     0xE => {
                         // (Optional Step) Set Vx to Vy
-                        //self.variables[x as usize] = self.variables[y as usize];
+                        if quirks.shift_uses_vy {
+                            self.variables[x as usize] = self.variables[y as usize];
+                        }
                         // Set flag bit to the bit that will get shifted out
-                        chip8.variables[15] = (chip8.variables[x as usize] >> 7) & 0x1;
+                        let flag = (self.variables[x as usize] >> 7) & 0x1;
                         // Shift Vx one to the left
-                        chip8.variables[x as usize] = chip8.variables[x as usize] << 1;
+                        self.variables[x as usize] <<= 1;
+                        self.variables[15] = flag;
                     }
 
                     _ => {
-                        panic!("Opcode {} n = {} not found", op, n);
+                        return Err(unknown);
                     }
                 }
             }
             //BNNN Jump With Offset (Ambigious Instruction)
             0xB => {
-                // Jump to the address NNN + the value in v0
-                chip8.pc = addr + chip8.variables[0] as u16;
+                // Jump to the address NNN + the value in v0, or XNN + the value in vx
+                let offset = if quirks.jump_uses_vx { self.variables[x as usize] } else { self.variables[0] };
+                self.pc = addr + offset as u16;
             }
             // CXNN Random
             0xC => {
                 // Generate a random number and AND it with NN and store result in Vx
-                let random_u8: u8 = rand::thread_rng().gen();
-                chip8.variables[x as usize] = byte & random_u8;
+                let random_u8 = self.rng.byte();
+                self.variables[x as usize] = byte & random_u8;
             }
             // Skip if Key Instructions
             0xE => {
                 match byte {
                     // Skip an instruction if key in Vx is pressed
                     0x9E => {
-                        log::info!("Skipping if key: {} is pressed.",chip8.variables[x as usize]);
-                       
-                        if pressed_keys.contains(&keys[chip8.variables[x as usize] as usize]){
-                            chip8.pc = chip8.pc + 2;
+                        log::info!("Skipping if key: {} is pressed.",self.variables[x as usize]);
+                        if self.keypad[(self.variables[x as usize] & 0xF) as usize] {
+                            self.skip();
                         }
                     }
                     // Skip an instruction if key in Vx is NOT pressed
                     0xA1 => {
-                        log::info!("Skipping if key: {} is NOT pressed.",chip8.variables[x as usize]);
-                        
-                        if !pressed_keys.contains(&keys[chip8.variables[x as usize] as usize]){
-                            chip8.pc = chip8.pc + 2;
+                        log::info!("Skipping if key: {} is NOT pressed.",self.variables[x as usize]);
+                        if !self.keypad[(self.variables[x as usize] & 0xF) as usize] {
+                            self.skip();
                         }
                     }
 
                     _ => {
-                        panic!("Opcode {} n = {} not found", op, n);
+                        return Err(unknown);
                     }
                 }
             }
             // Misc Instructions
             0xF => {
                 match byte {
+                    // XO-CHIP F000 NNNN Load the following 16-bit word into I
+                    0x00 if xo_chip && x == 0 => {
+                        let high = self.mem_read(start_pc, self.pc as usize)?;
+                        let low = self.mem_read(start_pc, self.pc as usize + 1)?;
+                        self.index = ((high as u16) << 8) | low as u16;
+                        self.pc = self.pc.wrapping_add(2);
+                    }
+
+                    // XO-CHIP FN01 Select the bit-planes N to draw on
+                    0x01 if xo_chip => {
+                        self.plane = x & 0x3;
+                    }
+
+                    // XO-CHIP F002 Load the 16 byte audio pattern at I
+                    0x02 if xo_chip && x == 0 => {
+                        self.check_range(start_pc, self.index as usize, 16)?;
+                        let mut pattern = [0; 16];
+                        pattern.copy_from_slice(&self.memory[self.index as usize..self.index as usize + 16]);
+                        self.audio_pattern = Some(pattern);
+                    }
+
+                    // XO-CHIP FX3A Set the audio pattern pitch to Vx
+                    0x3A if xo_chip => {
+                        self.pitch = self.variables[x as usize];
+                    }
+
                     // FX07 Sets Vx to current value of delay timer
                     0x07 => {
-                        chip8.variables[x as usize] = chip8.delay_timer;
-                        log::info!("Delay_timer = {}",chip8.delay_timer);
-                        log::info!("Vx = {}",chip8.variables[x as usize]);
+                        self.variables[x as usize] = self.delay_timer;
+                        log::info!("Delay_timer = {}",self.delay_timer);
+                        log::info!("Vx = {}",self.variables[x as usize]);
                     }
 
                     // FX15 Sets the delay timer to the value in Vx
                     0x15 => {
-                        chip8.delay_timer = chip8.variables[ x as usize];
+                        self.delay_timer = self.variables[ x as usize];
                     }
 
                     // FX18 Sets the sound timer to the value in Vx
                     0x18 => {
-                        chip8.sound_timer = chip8.variables[x as usize];
+                        self.sound_timer = self.variables[x as usize];
                     }
 
                     // FX1E Add to index
                     0x1E => {
-                        chip8.index = chip8.index + chip8.variables[x as usize] as u16;
+                        self.index = self.index.wrapping_add(self.variables[x as usize] as u16);
                     }
 
                     // FX0A Get key
                     0x0A => {
                         log::info!("Waiting on key press...");
-                        
-                            // If a key is pressed, store that keycode and exit the loop
-                                if pressed_keys.len() != 0{
-                                    chip8.variables[x as usize] = match pressed_keys[0]{
-                                        Key1 => {0x1}
-                                        Key2 => {0x2}
-                                        Key3 => {0x3}
-                                        Key4 => {0xC}
-                                        Q => {0x4}
-                                        W => {0x5}
-                                        E => {0x6}
-                                        R => {0xD}
-                                        A => {0x7}
-                                        S => {0x8}
-                                        D => {0x9}
-                                        F => {0xE}
-                                        Z => {0xA}
-                                        X => {0x0}
-                                        C => {0xB}
-                                        V => {0xF}
-                                        _=> panic!("Could not find key {}",pressed_keys[0])
-                                    };
-                            log::info!("Captured key input {}",chip8.variables[x as usize]);
-                            
+                        // If a key is pressed, store that key and move on,
+                        // otherwise repeat this instruction.
+                        match self.keypad.iter().position(|&pressed| pressed) {
+                            Some(key) => {
+                                self.variables[x as usize] = key as u8;
+                                log::info!("Captured key input {}",self.variables[x as usize]);
                             }
-                            else{
-                                chip8.pc = chip8.pc - 2;
+                            None => {
+                                self.pc = start_pc;
+                                step.waiting_for_key = true;
                             }
+                        }
                     }
 
                     // FX29 Font character
                     0x29 => {
                         // Setting the index register to the address of the font character in Vx
-                        chip8.index = 0x050 as u16 + ( chip8.variables[x as usize] * 5 ) as u16
+                        self.index = FONT_START + (self.variables[x as usize] & 0xF) as u16 * 5;
+                    }
+
+                    // SUPER-CHIP FX30 Big font character
+                    0x30 if extended => {
+                        self.index = BIG_FONT_START + (self.variables[x as usize] & 0xF) as u16 * 10;
+                    }
+
+                    // SUPER-CHIP FX75 Save V0-Vx to the RPL user flags
+                    0x75 if extended => {
+                        self.rpl[..=x as usize].copy_from_slice(&self.variables[..=x as usize]);
+                    }
+
+                    // SUPER-CHIP FX85 Load V0-Vx from the RPL user flags
+                    0x85 if extended => {
+                        self.variables[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
                     }
 
                     // FX33 Binary-coded decimal conversion
                     0x33 => {
-                        // This should take the number in Vx (0-255) and convert it to 3 digits 
+                        // This should take the number in Vx (0-255) and convert it to 3 digits
                         // and stores them in memory starting at the address in the index register.
-                        let mut number = chip8.variables[x as usize];
+                        let mut number = self.variables[x as usize];
                         for i in 0..3{
                             let digit = (number) % 10;
-                            number = number / 10;
-                            let chip8_index = chip8.index.clone();
-                            chip8.memory[(chip8_index as usize + 2 - i )] = digit;
-                            
+                            number /= 10;
+                            self.mem_write(start_pc, self.index as usize + 2 - i, digit)?;
                         }
                     }
 
                     // FX55 Store memory (Ambiguous Instruction)
                     0x55 => {
-                        //println!("STORED");
+                        self.check_range(start_pc, self.index as usize, x as usize + 1)?;
                         for i in 0..(x as usize)+1{
-                            let chip8_index = chip8.index.clone();
-                            chip8.memory[chip8_index as usize + i] = chip8.variables[i];
+                            self.memory[self.index as usize + i] = self.variables[i];
                         }
+                        self.increment_index(x);
                     }
 
                     // FX65 Load memory (Ambiguous Instruction)
                     0x65 => {
-                        //println!("LOADED");
+                        self.check_range(start_pc, self.index as usize, x as usize + 1)?;
                         for i in 0..(x as usize)+1{
-                            chip8.variables[i] = chip8.memory[chip8.index as usize + i];
+                            self.variables[i] = self.memory[self.index as usize + i];
                         }
-
+                        self.increment_index(x);
                     }
 
                     _ => {
-                        panic!("Opcode {} n = {} not found", op, n);
-                    }   
+                        return Err(unknown);
+                    }
                 }
             }
 
             // Catch-all for unrecognized instructions
             _ => {
-                panic!("Opcode {} not found", op);
+                return Err(unknown);
+            }
+        }
+        Ok(step)
+    }
+
+    // Skips over the next instruction, which on XO-CHIP may be the 4 byte F000 NNNN
+    fn skip(&mut self) {
+        let long = self.config.platform == Platform::XoChip
+            && self.memory.get(self.pc as usize) == Some(&0xF0)
+            && self.memory.get(self.pc as usize + 1) == Some(&0x00);
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    // XORs a sprite from memory onto one display plane with its top left corner
+    // at (x_pos, y_pos), returning the number of rows that turned a pixel off
+    // and the number clipped off the bottom of the screen
+    fn draw_sprite(&mut self, sprite: usize, x_pos: usize, y_pos: usize, sprite_width: usize, sprite_height: usize, plane: u8) -> (usize, usize) {
+        let clip = self.config.quirks.clip_sprites;
+        let width = self.display.width();
+        let height = self.display.height();
+        let bytes_per_row = sprite_width / 8;
+        let (mut collided_rows, mut clipped_rows) = (0, 0);
+        // For each row of the sprite, stopping if we reach the bottom of the screen
+        // when clipping or wrapping around to the top otherwise
+        for row in 0..sprite_height {
+            let mut current_y_pos = y_pos + row;
+            if current_y_pos >= height {
+                if clip {
+                    log::info!("Broke @ y = {}.",current_y_pos);
+                    clipped_rows = sprite_height - row;
+                    break;
+                }
+                current_y_pos %= height;
+            }
+            // Getting a row of sprite data from the sprite address
+            // This is the n'th sprite data row corresponding to the n'th row
+            let row_start = sprite + row * bytes_per_row;
+            let row_of_sprite_data = self.memory[row_start..row_start + bytes_per_row]
+                .iter()
+                .fold(0u16, |data, &byte| (data << 8) | byte as u16);
+            let mut collided = false;
+            // Iterating over the bits in the chosen sprite row as boolean value "bit"
+            for (column, bit) in (0..sprite_width).rev().map(|i| (row_of_sprite_data >> i) & 1 == 1).enumerate() {
+                // If we reach the right edge of the screen, stop drawing the current row
+                // when clipping or wrap around to the left edge otherwise
+                let mut current_x_pos = x_pos + column;
+                if current_x_pos >= width {
+                    if clip {
+                        log::info!("Broke @ x = {}.",current_x_pos);
+                        break;
+                    }
+                    current_x_pos %= width;
+                }
+                // XOR the sprite pixel onto the screen, noting if that turned a pixel off
+                if bit && self.display.toggle(current_x_pos, current_y_pos, plane) {
+                    collided = true;
+                }
             }
+            collided_rows += collided as usize;
+            log::info!("Drew line @ y = {}.",current_y_pos);
         }
-        
-        drop(chip8);
+        (collided_rows, clipped_rows)
     }
-}
\ No newline at end of file
+
+    // Playback rate in Hz of the bits in the XO-CHIP audio pattern
+    pub fn pattern_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    // Moves the index register past the registers stored/loaded by FX55/FX65
+    fn increment_index(&mut self, x: u8) {
+        self.index = match self.config.quirks.load_store_index {
+            IndexIncrement::Unchanged => self.index,
+            IndexIncrement::ByX => self.index.wrapping_add(x as u16),
+            IndexIncrement::ByXPlusOne => self.index.wrapping_add(x as u16 + 1),
+        };
+    }
+
+    // Captures the whole machine state, everything except the keypad which
+    // belongs to whoever is providing input
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            config: self.config,
+            memory: self.memory.clone(),
+            pc: self.pc,
+            index: self.index,
+            stack: self.stack.clone(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            variables: self.variables,
+            display: self.display.clone(),
+            rpl: self.rpl,
+            vblank: self.vblank,
+            plane: self.plane,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rng: self.rng,
+        }
+    }
+
+    // Puts the machine back into the state a snapshot captured, so it carries
+    // on exactly as it did after the snapshot was taken
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let keypad = self.keypad;
+        *self = CHIP8::with_config(snapshot.config);
+        self.memory.clone_from(&snapshot.memory);
+        self.pc = snapshot.pc;
+        self.index = snapshot.index;
+        self.stack.clone_from(&snapshot.stack);
+        self.delay_timer = snapshot.delay_timer;
+        self.sound_timer = snapshot.sound_timer;
+        self.variables = snapshot.variables;
+        self.display.clone_from(&snapshot.display);
+        self.keypad = keypad;
+        self.rpl = snapshot.rpl;
+        self.vblank = snapshot.vblank;
+        self.plane = snapshot.plane;
+        self.audio_pattern = snapshot.audio_pattern;
+        self.pitch = snapshot.pitch;
+        self.rng = snapshot.rng;
+    }
+
+    // Decrements the delay and sound timers and starts a new frame, called at 60Hz
+    pub fn tick_timers(&mut self) {
+        self.vblank = true;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+}
+
+// The registers Vx through Vy used by the XO-CHIP 5XY2/5XY3 instructions,
+// counting down when x is greater than y
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    if x <= y {
+        (x as usize..=y as usize).collect()
+    } else {
+        (y as usize..=x as usize).rev().collect()
+    }
+}
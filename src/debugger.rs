@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use crate::cpu::{CHIP8, Run, PROGRAM_START};
+use crate::input::Command;
+use crate::rewind::Rewind;
+
+// Execution control for the debugger pane: pausing, single-stepping,
+// stepping over subroutine calls, running to the cursor and PC breakpoints.
+// Stepping back replays from the checkpoints kept in a Rewind, which the
+// debugger adds to as it runs instructions.
+// The panels themselves are drawn by `render`; this only decides which
+// instructions run each frame.
+
+// A temporary stop set by step-over and run-to-cursor. It triggers when the
+// program counter reaches `addr` with at most `depth` return addresses on the
+// stack, so a recursive call passing the same address does not stop early.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Target {
+    addr: u16,
+    depth: usize,
+}
+
+pub struct Debugger {
+    pub paused: bool,
+    pub breakpoints: BTreeSet<u16>,   // Addresses that pause execution before running
+    pub cursor: u16,                  // Address selected in the code panel
+    target: Option<Target>,           // Where to stop after a step-over or run-to-cursor
+    step: bool,                       // Whether one instruction should run while paused
+    resumed: bool,                    // Whether execution just resumed and has not run anything yet
+}
+
+impl Debugger {
+    pub fn new(paused: bool, breakpoints: impl IntoIterator<Item = u16>) -> Debugger {
+        Debugger {
+            paused,
+            breakpoints: breakpoints.into_iter().collect(),
+            cursor: PROGRAM_START,
+            target: None,
+            step: false,
+            resumed: false,
+        }
+    }
+
+    // Applies a debugger command from the keyboard; other commands are ignored
+    pub fn handle(&mut self, command: Command, chip8: &mut CHIP8, history: &mut Rewind) {
+        match command {
+            Command::Pause => {
+                if self.paused {
+                    self.resume();
+                } else {
+                    self.pause(chip8);
+                }
+            }
+            Command::Step if self.paused => self.step = true,
+            Command::StepBack if self.paused => {
+                if history.back_step(chip8) {
+                    self.cursor = chip8.pc;
+                } else {
+                    log::error!("No earlier instruction to step back to.");
+                }
+            }
+            Command::StepOver if self.paused => {
+                // Only 2NNN needs stepping over, anything else is a plain step
+                if opcode_at(chip8, chip8.pc) & 0xF000 == 0x2000 {
+                    self.run_to(chip8.pc.wrapping_add(2), chip8.stack.len());
+                } else {
+                    self.step = true;
+                }
+            }
+            Command::RunToCursor => self.run_to(self.cursor, usize::MAX),
+            Command::ToggleBreakpoint => self.toggle_breakpoint(self.cursor),
+            Command::MoveCursor(instructions) => {
+                self.cursor = self.cursor.wrapping_add_signed(instructions.saturating_mul(2));
+            }
+            Command::CursorToPc => self.cursor = chip8.pc,
+            _ => {}
+        }
+    }
+
+    // Sets a breakpoint at `addr`, or clears the one already there
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    // Resumes until the program counter reaches `addr` with no more than
+    // `depth` return addresses on the stack
+    fn run_to(&mut self, addr: u16, depth: usize) {
+        self.resume();
+        self.target = Some(Target { addr, depth });
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.resumed = true;
+        self.target = None;
+    }
+
+    // Runs the next frame under debugger control, in place of
+    // `CHIP8::run_frame`. While paused nothing runs and the timers stay
+    // frozen, except for a single requested step. Errors pause execution on
+    // the faulting instruction instead of ending the session.
+    pub fn run_frame(&mut self, chip8: &mut CHIP8, history: &mut Rewind) -> Run {
+        let mut run = Run::default();
+        if self.paused {
+            if std::mem::take(&mut self.step) {
+                history.checkpoint(chip8);
+                match chip8.step() {
+                    Ok(step) => {
+                        run.record(step);
+                        history.record(&run);
+                        // A draw held back by the display wait quirk needs the
+                        // next frame to begin before stepping again can draw it
+                        if step.waiting_for_vblank {
+                            chip8.tick_timers();
+                        }
+                    }
+                    Err(error) => log::error!("{}", error),
+                }
+                self.cursor = chip8.pc;
+            }
+            return run;
+        }
+
+        history.checkpoint(chip8);
+        for _ in 0..chip8.config.cycles_per_frame() {
+            // The instruction execution resumed on always runs, so continuing
+            // from a breakpoint does not stop on it again straight away
+            if !std::mem::take(&mut self.resumed) && self.should_stop(chip8) {
+                break;
+            }
+            match chip8.step() {
+                Ok(step) => run.record(step),
+                Err(error) => {
+                    log::error!("{}", error);
+                    self.pause(chip8);
+                    break;
+                }
+            }
+            if run.waiting_for_key || run.waiting_for_vblank || run.exited {
+                break;
+            }
+        }
+        history.record(&run);
+        // Stopping part way through a frame still lets that frame's time pass
+        chip8.tick_timers();
+        run
+    }
+
+    // Pauses if execution should stop before the instruction at the program
+    // counter, returning whether it did
+    fn should_stop(&mut self, chip8: &CHIP8) -> bool {
+        let hit_target = self.target.is_some_and(|target| target.addr == chip8.pc && chip8.stack.len() <= target.depth);
+        if hit_target || self.breakpoints.contains(&chip8.pc) {
+            self.pause(chip8);
+        }
+        self.paused
+    }
+
+    fn pause(&mut self, chip8: &CHIP8) {
+        self.paused = true;
+        self.target = None;
+        self.cursor = chip8.pc;
+    }
+}
+
+// The instruction word stored at `addr`, or zero past the end of memory
+pub fn opcode_at(chip8: &CHIP8, addr: u16) -> u16 {
+    let addr = addr as usize;
+    match chip8.memory.get(addr..addr + 2) {
+        Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+        None => 0,
+    }
+}
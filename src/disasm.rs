@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use crate::quirks::Platform;
+
+// Turns CHIP-8 machine code back into assembly text, using the mnemonics from
+// Cowgod's technical reference plus the SUPER-CHIP and XO-CHIP extensions.
+// Only encodings the interpreter accepts for the given platform are decoded;
+// every other word comes out as a `DW` directive so the listing still
+// describes every byte, data included.
+
+// Technical References:
+// http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.1
+// https://johnearnest.github.io/Octo/docs/XO-ChipSpecification.html
+
+// One decoded instruction or data directive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,       // Address of the first byte
+    pub len: usize,      // Bytes covered: 2, 4 for the XO-CHIP long I load, 1 for a trailing odd byte
+    pub text: String,    // Mnemonic and operands, using labels for jump and call targets
+}
+
+// A disassembled address range along with the labels given to the jump and
+// call targets inside it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+// Decodes the instruction at `addr`, writing jump and call targets as plain
+// addresses. Returns the text and the number of bytes it covers.
+pub fn decode(memory: &[u8], addr: u16, platform: Platform) -> (String, usize) {
+    decode_with(memory, addr, platform, &|target| format!("0x{:03X}", target))
+}
+
+// Disassembles `range` of memory from start to end, giving every jump and
+// call target that starts one of the listed instructions a label
+pub fn disassemble(memory: &[u8], range: Range<usize>, platform: Platform) -> Disassembly {
+    let range = range.start..range.end.min(memory.len());
+    let mut addrs = Vec::new();
+    let mut targets = BTreeMap::new();
+    let mut addr = range.start;
+    while addr < range.end {
+        let (_, len) = decode(&memory[..range.end], addr as u16, platform);
+        addrs.push(addr as u16);
+        if len == 2 {
+            if let Some((target, call)) = branch_target(opcode(memory, addr as u16)) {
+                // A call label wins over a jump label for the same address
+                *targets.entry(target).or_insert(call) |= call;
+            }
+        }
+        addr += len;
+    }
+
+    let labels: BTreeMap<u16, String> = targets
+        .into_iter()
+        .filter(|(target, _)| addrs.binary_search(target).is_ok())
+        .map(|(target, call)| (target, format!("{}_{:03X}", if call { "sub" } else { "loc" }, target)))
+        .collect();
+    let name = |target: u16| labels.get(&target).cloned().unwrap_or_else(|| format!("0x{:03X}", target));
+    let lines = addrs
+        .into_iter()
+        .map(|addr| {
+            let (text, len) = decode_with(&memory[..range.end], addr, platform, &name);
+            Line { addr, len, text }
+        })
+        .collect();
+    Disassembly { lines, labels }
+}
+
+impl fmt::Display for Disassembly {
+    // Writes assembler source: each label on its own line, then one
+    // instruction per line with its address in a comment
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            writeln!(f, "    {:<24}; {:03X}", line.text, line.addr)?;
+        }
+        Ok(())
+    }
+}
+
+// The big-endian word at `addr`, with missing bytes read as zero
+fn opcode(memory: &[u8], addr: u16) -> u16 {
+    let byte = |addr: usize| memory.get(addr).copied().unwrap_or(0) as u16;
+    (byte(addr as usize) << 8) | byte(addr as usize + 1)
+}
+
+// The address a 1NNN, 2NNN or BNNN instruction branches to, and whether it
+// is a subroutine call
+fn branch_target(opcode: u16) -> Option<(u16, bool)> {
+    let target = opcode & 0x0FFF;
+    match opcode >> 12 {
+        0x1 | 0xB => Some((target, false)),
+        0x2 => Some((target, true)),
+        _ => None,
+    }
+}
+
+fn decode_with(memory: &[u8], addr: u16, platform: Platform, name: &dyn Fn(u16) -> String) -> (String, usize) {
+    let start = addr as usize;
+    if start + 1 >= memory.len() {
+        // A lone byte left over at the end
+        return (format!("DB 0x{:02X}", memory.get(start).copied().unwrap_or(0)), 1);
+    }
+    let word = opcode(memory, addr);
+    // XO-CHIP F000 NNNN takes the whole following word as the address
+    if platform == Platform::XoChip && word == 0xF000 && start + 3 < memory.len() {
+        return (format!("LD I, LONG 0x{:04X}", opcode(memory, addr + 2)), 4);
+    }
+    match mnemonic(word, platform, name) {
+        Some(text) => (text, 2),
+        None => (format!("DW 0x{:04X}", word), 2),
+    }
+}
+
+// The assembly text for a single two byte instruction, or None when the
+// platform does not define it
+fn mnemonic(opcode: u16, platform: Platform, name: &dyn Fn(u16) -> String) -> Option<String> {
+    let extended = platform != Platform::Chip8;
+    let xo_chip = platform == Platform::XoChip;
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let byte = opcode & 0xFF;
+    let addr = opcode & 0xFFF;
+
+    Some(match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xE, 0x0) => String::from("CLS"),
+        (0x0, 0x0, 0xE, 0xE) => String::from("RET"),
+        (0x0, 0x0, 0xC, _) if extended => format!("SCD {}", n),
+        (0x0, 0x0, 0xD, _) if xo_chip => format!("SCU {}", n),
+        (0x0, 0x0, 0xF, 0xB) if extended => String::from("SCR"),
+        (0x0, 0x0, 0xF, 0xC) if extended => String::from("SCL"),
+        (0x0, 0x0, 0xF, 0xD) if extended => String::from("EXIT"),
+        (0x0, 0x0, 0xF, 0xE) if extended => String::from("LOW"),
+        (0x0, 0x0, 0xF, 0xF) if extended => String::from("HIGH"),
+        (0x1, ..) => format!("JP {}", name(addr)),
+        (0x2, ..) => format!("CALL {}", name(addr)),
+        (0x3, ..) => format!("SE V{:X}, 0x{:02X}", x, byte),
+        (0x4, ..) => format!("SNE V{:X}, 0x{:02X}", x, byte),
+        (0x5, _, _, 0x0) => format!("SE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x2) if xo_chip => format!("SAVE V{:X}, V{:X}", x, y),
+        (0x5, _, _, 0x3) if xo_chip => format!("LOAD V{:X}, V{:X}", x, y),
+        (0x6, ..) => format!("LD V{:X}, 0x{:02X}", x, byte),
+        (0x7, ..) => format!("ADD V{:X}, 0x{:02X}", x, byte),
+        (0x8, _, _, 0x0) => format!("LD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x1) => format!("OR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x2) => format!("AND V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x3) => format!("XOR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x4) => format!("ADD V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x5) => format!("SUB V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x6) => format!("SHR V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0x7) => format!("SUBN V{:X}, V{:X}", x, y),
+        (0x8, _, _, 0xE) => format!("SHL V{:X}, V{:X}", x, y),
+        (0x9, _, _, 0x0) => format!("SNE V{:X}, V{:X}", x, y),
+        (0xA, ..) => format!("LD I, {}", name(addr)),
+        (0xB, ..) => format!("JP V0, {}", name(addr)),
+        (0xC, ..) => format!("RND V{:X}, 0x{:02X}", x, byte),
+        (0xD, ..) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("SKP V{:X}", x),
+        (0xE, _, 0xA, 0x1) => format!("SKNP V{:X}", x),
+        (0xF, _, 0x0, 0x1) if xo_chip => format!("PLANE {}", x),
+        (0xF, 0x0, 0x0, 0x2) if xo_chip => String::from("AUDIO"),
+        (0xF, _, 0x3, 0xA) if xo_chip => format!("PITCH V{:X}", x),
+        (0xF, _, 0x0, 0x7) => format!("LD V{:X}, DT", x),
+        (0xF, _, 0x0, 0xA) => format!("LD V{:X}, K", x),
+        (0xF, _, 0x1, 0x5) => format!("LD DT, V{:X}", x),
+        (0xF, _, 0x1, 0x8) => format!("LD ST, V{:X}", x),
+        (0xF, _, 0x1, 0xE) => format!("ADD I, V{:X}", x),
+        (0xF, _, 0x2, 0x9) => format!("LD F, V{:X}", x),
+        (0xF, _, 0x3, 0x0) if extended => format!("LD HF, V{:X}", x),
+        (0xF, _, 0x3, 0x3) => format!("LD B, V{:X}", x),
+        (0xF, _, 0x5, 0x5) => format!("LD [I], V{:X}", x),
+        (0xF, _, 0x6, 0x5) => format!("LD V{:X}, [I]", x),
+        (0xF, _, 0x7, 0x5) if extended => format!("LD R, V{:X}", x),
+        (0xF, _, 0x8, 0x5) if extended => format!("LD V{:X}, R", x),
+        _ => return None,
+    })
+}
@@ -0,0 +1,138 @@
+// The CHIP-8 framebuffer. Plain CHIP-8 programs see a 64x32 display, while
+// SUPER-CHIP programs can switch it into a 128x64 high resolution mode, so the
+// size is only known at runtime.
+
+// XO-CHIP adds a second bit-plane, so every pixel holds a 2-bit value: bit 0
+// for plane 1 and bit 1 for plane 2, giving four colours. Drawing, clearing
+// and scrolling only touch the planes selected in a plane mask.
+
+use std::fmt;
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,     // Row-major plane bits, `width` pixels per row
+}
+
+impl Default for Display {
+    fn default() -> Display {
+        Display::new(LORES_WIDTH, LORES_HEIGHT)
+    }
+}
+
+impl Display {
+    pub fn new(width: usize, height: usize) -> Display {
+        Display { width, height, pixels: vec![0; width * height] }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_WIDTH
+    }
+
+    // Switches between 64x32 and 128x64 mode, clearing the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        *self = if hires {
+            Display::new(HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            Display::new(LORES_WIDTH, LORES_HEIGHT)
+        };
+    }
+
+    // Whether the pixel is lit on any plane
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.pixel(x, y) != 0
+    }
+
+    // The plane bits of a pixel, 0-3
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, planes: u8) {
+        self.pixels[y * self.width + x] = planes;
+    }
+
+    // XORs a sprite pixel onto the given planes, returning true if it turned a pixel off
+    pub fn toggle(&mut self, x: usize, y: usize, planes: u8) -> bool {
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collided = *pixel & planes != 0;
+        *pixel ^= planes;
+        collided
+    }
+
+    // Blanks the given planes of every pixel
+    pub fn clear(&mut self, planes: u8) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    // Iterates over the rows of the display from top to bottom
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    // Scrolling moves the picture on the given planes by `n` pixels of the
+    // current resolution, filling the uncovered area with blank pixels.
+
+    pub fn scroll_down(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        self.shift(planes, |x, y| (y >= n).then(|| (x, y - n)));
+    }
+
+    pub fn scroll_up(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.height);
+        let height = self.height;
+        self.shift(planes, |x, y| (y + n < height).then(|| (x, y + n)));
+    }
+
+    pub fn scroll_right(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        self.shift(planes, |x, y| (x >= n).then(|| (x - n, y)));
+    }
+
+    pub fn scroll_left(&mut self, n: usize, planes: u8) {
+        let n = n.min(self.width);
+        let width = self.width;
+        self.shift(planes, |x, y| (x + n < width).then(|| (x + n, y)));
+    }
+
+    // Rebuilds the given planes by copying each pixel from the position
+    // `source` maps it to, or blanking it when there is none
+    fn shift(&mut self, planes: u8, source: impl Fn(usize, usize) -> Option<(usize, usize)>) {
+        let old = self.pixels.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let moved = source(x, y).map_or(0, |(sx, sy)| old[sy * self.width + sx]);
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = (*pixel & !planes) | (moved & planes);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Display {
+    // Draws the screen as text, one line per row: '.' for blank pixels, '#'
+    // for plane 1, '+' for plane 2 and '@' where both planes are lit
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for row in self.rows() {
+            let line: String = row.iter().map(|&pixel| ['.', '#', '+', '@'][pixel as usize & 0x3]).collect();
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
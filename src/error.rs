@@ -0,0 +1,76 @@
+use std::fmt;
+use std::io;
+
+// Errors raised by the CHIP-8 core. These are returned from the step and load
+// APIs instead of panicking, so a frontend can report them and halt cleanly.
+#[derive(Debug)]
+pub enum Chip8Error {
+    UnknownOpcode { addr: u16, opcode: u16 },   // Instruction at `addr` could not be decoded
+    StackUnderflow { addr: u16 },              // 00EE at `addr` with an empty stack
+    StackOverflow { addr: u16 },               // 2NNN at `addr` with a full stack
+    MemoryOutOfRange { addr: u16, access: usize }, // Instruction at `addr` touched memory past the end of RAM
+    RomTooLarge { size: usize, max: usize },   // ROM does not fit in program memory
+    InvalidSnapshot(String),                   // Save state data is malformed or from an unknown version
+    InvalidMovie(String),                      // Movie data is malformed or does not fit the ROM being played
+    InvalidPalette(String),                    // Palette file does not name a palette or list its colours
+    InvalidFilter(String),                     // Display filter file does not name a filter
+    Io(io::Error),                             // A file could not be read or written
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { addr, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, addr)
+            }
+            Chip8Error::StackUnderflow { addr } => {
+                write!(f, "return with an empty stack at {:03X}", addr)
+            }
+            Chip8Error::StackOverflow { addr } => {
+                write!(f, "call stack overflow at {:03X}", addr)
+            }
+            Chip8Error::MemoryOutOfRange { addr, access } => {
+                write!(f, "memory access at {:X} out of range at {:03X}", access, addr)
+            }
+            Chip8Error::RomTooLarge { size, max } => {
+                write!(f, "ROM is {} bytes but only {} bytes of program memory are available", size, max)
+            }
+            Chip8Error::InvalidSnapshot(reason) => write!(f, "invalid save state: {}", reason),
+            Chip8Error::InvalidMovie(reason) => write!(f, "invalid movie: {}", reason),
+            Chip8Error::InvalidPalette(reason) => write!(f, "invalid palette: {}", reason),
+            Chip8Error::InvalidFilter(reason) => write!(f, "invalid display filter: {}", reason),
+            Chip8Error::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Chip8Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(error: io::Error) -> Chip8Error {
+        Chip8Error::Io(error)
+    }
+}
+
+// An error in assembler source, pointing at the line that caused it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,      // Source file the line came from
+    pub line: usize,       // Line number, counting from 1
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
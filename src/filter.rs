@@ -0,0 +1,116 @@
+use std::path::Path;
+use crate::display::Display;
+use crate::error::Chip8Error;
+
+// Display filters that hide the flicker of XOR-drawn sprites. CHIP-8
+// programs usually move a sprite by erasing it and drawing it again, so in
+// any one frame it may be missing altogether. Filters keep pixels lit for a
+// little while after the program turns them off, the way the slow phosphor
+// of old monitors did. They only change what the terminal shows; the
+// framebuffer, screenshots and videos are left alone.
+
+// Brightness steps between off and fully lit
+pub const LEVELS: u8 = 16;
+const FULL: u8 = LEVELS - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Off,
+    Merge,                      // Show pixels lit in this frame or the one before
+    Phosphor { decay: u8 },     // Fade pixels out, keeping `decay` percent of their brightness each frame
+}
+
+impl Filter {
+    // `off`, `merge`, `phosphor`, or `phosphor:<percent>` for a slower or
+    // faster fade than the default of half each frame
+    pub fn parse(spec: &str) -> Option<Filter> {
+        let spec = spec.trim().to_ascii_lowercase();
+        match spec.split_once(':') {
+            Some(("phosphor", decay)) => match decay.parse() {
+                Ok(decay) if decay < 100 => Some(Filter::Phosphor { decay }),
+                _ => None,
+            },
+            Some(_) => None,
+            None => match spec.as_str() {
+                "off" => Some(Filter::Off),
+                "merge" => Some(Filter::Merge),
+                "phosphor" => Some(Filter::Phosphor { decay: 50 }),
+                _ => None,
+            },
+        }
+    }
+
+    // Reads a filter file, which holds what `parse` accepts. Lines starting
+    // with `;` are comments.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Filter, Chip8Error> {
+        let text = std::fs::read_to_string(path)?;
+        let spec: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with(';')).collect();
+        Filter::parse(&spec.join(" ")).ok_or_else(|| {
+            Chip8Error::InvalidFilter(String::from("expected off, merge, phosphor or phosphor:<percent>"))
+        })
+    }
+}
+
+// A pixel as the renderer should show it: the plane bits it is lit with and
+// how brightly, from 0 to LEVELS - 1
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Glow {
+    pub level: u8,    // Compared first, so the brightest pixel sorts highest
+    pub planes: u8,
+}
+
+// Runs a filter over the display once per frame
+pub struct DisplayFilter {
+    filter: Filter,
+    width: usize,
+    height: usize,
+    previous: Vec<u8>,   // Merge: the plane bits of the frame before
+    glow: Vec<Glow>,
+}
+
+impl DisplayFilter {
+    pub fn new(filter: Filter) -> DisplayFilter {
+        DisplayFilter { filter, width: 0, height: 0, previous: Vec::new(), glow: Vec::new() }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn glow(&self, x: usize, y: usize) -> Glow {
+        self.glow[y * self.width + x]
+    }
+
+    // Takes in the display at the end of a frame, returning whether what
+    // the renderer shows changed
+    pub fn update(&mut self, display: &Display) -> bool {
+        let current: Vec<u8> = display.rows().flatten().copied().collect();
+        // A change of resolution starts over
+        if (display.width(), display.height()) != (self.width, self.height) {
+            self.width = display.width();
+            self.height = display.height();
+            self.previous = current.clone();
+            self.glow = vec![Glow::default(); current.len()];
+        }
+        let mut changed = false;
+        for ((glow, &planes), &previous) in self.glow.iter_mut().zip(&current).zip(&self.previous) {
+            let next = match self.filter {
+                Filter::Off => Glow { planes, level: FULL },
+                Filter::Merge => Glow { planes: planes | previous, level: FULL },
+                Filter::Phosphor { .. } if planes != 0 => Glow { planes, level: FULL },
+                Filter::Phosphor { decay } => {
+                    let level = (glow.level as u16 * decay as u16 / 100) as u8;
+                    Glow { planes: if level == 0 { 0 } else { glow.planes }, level }
+                }
+            };
+            changed |= next != *glow;
+            *glow = next;
+        }
+        self.previous = current;
+        changed
+    }
+}
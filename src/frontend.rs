@@ -0,0 +1,230 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+use crate::audio::{Tone, ToneControl, Voice, SAMPLE_RATE};
+use crate::cpu::{CHIP8, Run};
+use crate::debugger::Debugger;
+use crate::error::Chip8Error;
+use crate::render::ChipRender;
+use crate::input::{Command, Input};
+use crate::palette::Palette;
+use crate::rewind::Rewind;
+use crate::screenshot;
+use crate::video::Video;
+use crate::snapshot::Snapshot;
+use rodio::OutputStream;
+
+// The terminal frontend: drives a CHIP8 core from an Input backend, plays the
+// sound timer through rodio and draws the framebuffer with ChipRender.
+
+// Length of one 60Hz frame
+const FRAME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+// Plays the sound timer through a tone generator that runs for the whole
+// session. The stream is opened even when muted, so loading a state that is
+// not muted brings the sound back. Without an audio device the beeper stays
+// silent.
+struct Beeper {
+    output: Option<(OutputStream, ToneControl)>,   // The stream has to stay open while the tone plays
+}
+
+impl Beeper {
+    fn new() -> Beeper {
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok(output) => output,
+            Err(error) => {
+                log::error!("No audio device, sound disabled: {}", error);
+                return Beeper { output: None };
+            }
+        };
+        let tone = Tone::new(SAMPLE_RATE);
+        let control = tone.control();
+        if let Err(error) = handle.play_raw(tone) {
+            log::error!("Failed to play sound: {}", error);
+            return Beeper { output: None };
+        }
+        Beeper { output: Some((stream, control)) }
+    }
+
+    // Called once per frame, with whether the program ran in it
+    fn update(&self, chip8: &CHIP8, running: bool) {
+        if let Some((_stream, control)) = &self.output {
+            control.set(Voice::of(chip8, running));
+        }
+    }
+}
+
+// Frontend settings that stay fixed while a program runs
+pub struct Session {
+    pub state_path: PathBuf,       // File written by quick-save and read by quick-load
+    pub rewind_frames: usize,      // Frames of history kept for rewinding, zero to disable it
+    pub capture_path: PathBuf,     // Screenshots and videos are numbered files named after this path
+    pub image_scale: usize,        // Image pixels per CHIP-8 pixel in screenshots and videos
+    pub palette: Palette,          // Colours for screenshots and videos
+}
+
+// Runs the emulator until the user quits, the program exits or the CPU
+// reports an error.
+//
+// Everything happens on one thread in 60Hz frames: read the keyboard, execute
+// a frame worth of instructions, tick the timers, play sound, draw, and then
+// sleep for whatever is left of the frame. With a debugger the instructions
+// run under its control and the debugger panels are drawn every frame.
+//
+// Every frame starts with a checkpoint in the rewind history. Rewinding
+// restores one and skips running that frame, so holding the rewind key plays
+// the game backwards.
+//
+// `video` records every frame from the start. The video hotkey stops it, or
+// starts a new recording when none is running.
+pub fn run(
+    chip8: &mut CHIP8,
+    renderer: &mut ChipRender,
+    input: &mut dyn Input,
+    session: &Session,
+    mut debugger: Option<&mut Debugger>,
+    mut video: Option<Video>,
+) -> Result<(), Chip8Error> {
+    let beeper = Beeper::new();
+    let mut history = Rewind::new(session.rewind_frames);
+    let mut next_frame = Instant::now();
+
+    loop {
+        // Feeding the host keyboard state into the emulated keypad
+        let mut rewound = false;
+        for command in input.poll(&mut chip8.keypad) {
+            match (command, debugger.as_deref_mut()) {
+                (Command::Quit, _) => {
+                    log::info!("Quit key pressed, program exited.");
+                    return Ok(());
+                }
+                (Command::QuickSave, _) => quick_save(chip8, session),
+                (Command::QuickLoad, _) => quick_load(chip8, session, &mut history),
+                (Command::Screenshot, _) => take_screenshot(chip8, session),
+                (Command::Video, _) => toggle_video(&mut video, session),
+                (Command::Rewind, debugger) => {
+                    rewound |= history.back_frame(chip8);
+                    if let Some(debugger) = debugger {
+                        debugger.handle(Command::CursorToPc, chip8, &mut history);
+                    }
+                }
+                (command, Some(debugger)) => debugger.handle(command, chip8, &mut history),
+                (_, None) => {}
+            }
+        }
+
+        let run = match debugger.as_deref_mut() {
+            _ if rewound => Run::default(),
+            Some(debugger) => debugger.run_frame(chip8, &mut history),
+            None => {
+                history.checkpoint(chip8);
+                let run = chip8.run_frame()?;
+                history.record(&run);
+                run
+            }
+        };
+        // Pausing in the debugger or waiting out a rewind silences the tone
+        beeper.update(chip8, run.cycles > 0);
+        if let Some(recording) = video.as_mut() {
+            if let Err(error) = recording.frame(&chip8.display) {
+                log::error!("Failed to record video, stopped recording: {}", error);
+                video = None;
+            }
+        }
+        // Only redrawing once per frame, and only when something changed,
+        // which includes pixels a display filter is still fading out
+        let filtered = renderer.update(&chip8.display);
+        if chip8.display_dirty || filtered || renderer.resized() || debugger.is_some() {
+            renderer.render(chip8, debugger.as_deref());
+            chip8.display_dirty = false;
+        }
+        if run.exited {
+            log::info!("Program exited.");
+            return Ok(());
+        }
+
+        // Sleeping until the next frame is due. If we fell more than a frame
+        // behind (e.g. the terminal stalled) start counting again from now
+        // rather than racing to catch up.
+        next_frame += FRAME;
+        let now = Instant::now();
+        if next_frame > now {
+            spin_sleep::sleep(next_frame - now);
+        } else if now - next_frame > FRAME {
+            next_frame = now;
+        }
+    }
+}
+
+fn quick_save(chip8: &CHIP8, session: &Session) {
+    match chip8.snapshot().save(&session.state_path) {
+        Ok(()) => log::info!("Saved state to {}.", session.state_path.display()),
+        Err(error) => log::error!("Failed to save state to {}: {}", session.state_path.display(), error),
+    }
+}
+
+fn quick_load(chip8: &mut CHIP8, session: &Session, history: &mut Rewind) {
+    match Snapshot::load(&session.state_path) {
+        Ok(snapshot) => {
+            chip8.restore(&snapshot);
+            // The history leads up to the state being replaced, not this one
+            history.clear();
+            log::info!("Loaded state from {}.", session.state_path.display());
+        }
+        Err(error) => log::error!("Failed to load state from {}: {}", session.state_path.display(), error),
+    }
+}
+
+fn take_screenshot(chip8: &CHIP8, session: &Session) {
+    let path = numbered_path(session, "png");
+    match screenshot::save(&chip8.display, &path, session.image_scale, &session.palette) {
+        Ok(()) => log::info!("Saved screenshot to {}.", path.display()),
+        Err(error) => log::error!("Failed to save screenshot to {}: {}", path.display(), error),
+    }
+}
+
+fn toggle_video(video: &mut Option<Video>, session: &Session) {
+    match video.take() {
+        Some(mut recording) => match recording.finish() {
+            Ok(()) => log::info!("Stopped recording video after {} frames.", recording.frames()),
+            Err(error) => log::error!("Failed to finish video: {}", error),
+        },
+        None => {
+            let path = numbered_path(session, "gif");
+            match Video::create(&path, session.image_scale, &session.palette) {
+                Ok(recording) => {
+                    log::info!("Recording video to {}.", path.display());
+                    *video = Some(recording);
+                }
+                Err(error) => log::error!("Failed to record video to {}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+// The first of `<name>-1.<extension>`, `<name>-2.<extension>` and so on that
+// does not exist yet
+fn numbered_path(session: &Session, extension: &str) -> PathBuf {
+    let name = session.capture_path.file_name().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| session.capture_path.with_file_name(format!("{}-{}.{}", name, n, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// Runs a fixed number of frames as fast as possible with no keys pressed and
+// prints the final display, for scripts and CI where there is no terminal.
+// `after_frame` sees the machine after each frame, e.g. to record a video.
+pub fn run_headless<F>(chip8: &mut CHIP8, frames: u64, mut after_frame: F) -> Result<(), Chip8Error>
+where
+    F: FnMut(&CHIP8) -> Result<(), Chip8Error>,
+{
+    for _ in 0..frames {
+        let run = chip8.run_frame()?;
+        after_frame(chip8)?;
+        if run.exited {
+            break;
+        }
+    }
+    print!("{}", chip8.display);
+    Ok(())
+}
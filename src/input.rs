@@ -0,0 +1,211 @@
+use std::io;
+use std::time::{Duration, Instant};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+use crossterm::event::{PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags};
+use crossterm::{execute, terminal};
+
+// Keyboard input for the terminal frontend. Backends turn host key presses
+// into the state of the 16-key hex keypad plus frontend commands like quit.
+
+// Something the user asked the frontend itself to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    QuickSave,           // Save the machine state to the quick-save file
+    QuickLoad,           // Restore the machine state from the quick-save file
+    Rewind,              // Go back one frame in the rewind buffer
+    Screenshot,          // Write the display to an image file next to the ROM
+    Video,               // Start or stop recording the display to a GIF next to the ROM
+    Pause,               // Debugger: pause or resume execution
+    Step,                // Debugger: run one instruction while paused
+    StepOver,            // Debugger: step, running any 2NNN call through to its return
+    StepBack,            // Debugger: undo the last instruction while paused
+    RunToCursor,         // Debugger: resume until the cursor address is reached
+    ToggleBreakpoint,    // Debugger: set or clear a breakpoint at the cursor
+    MoveCursor(i16),     // Debugger: move the cursor by a number of instructions
+    CursorToPc,          // Debugger: move the cursor back to the program counter
+}
+
+pub trait Input {
+    // Updates `keypad` with the hex keys currently held down and returns any
+    // commands issued since the last poll. Called once per frame.
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command>;
+}
+
+// Reads key events from the terminal through crossterm, so it works over SSH
+// and without a display server, and only while the terminal has focus.
+//
+// Terminals normally only report key presses. Where the kitty keyboard
+// protocol is available we ask for release events too; otherwise a key counts
+// as held until `hold_timeout` passes without the terminal repeating it.
+pub struct CrosstermInput {
+    keymap: [char; 16],
+    hold_timeout: Duration,
+    key_releases: bool,                  // Whether the terminal reports key releases
+    pressed: [Option<Instant>; 16],      // When each hex key was last seen pressed
+}
+
+impl CrosstermInput {
+    // Must be created once the terminal is in raw mode
+    pub fn new(keymap: [char; 16], hold_timeout: Duration) -> CrosstermInput {
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false)
+            && execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )
+            .is_ok();
+        log::info!("Terminal key release events {}.", if key_releases { "enabled" } else { "unavailable" });
+        CrosstermInput { keymap, hold_timeout, key_releases, pressed: [None; 16] }
+    }
+
+    // Handles every event waiting in the terminal's queue without blocking
+    fn read_events(&mut self, commands: &mut Vec<Command>) -> io::Result<()> {
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc if key.kind == KeyEventKind::Press => commands.push(Command::Quit),
+                _ if ctrl_c => commands.push(Command::Quit),
+                KeyCode::Char(c) => {
+                    let c = c.to_ascii_lowercase();
+                    if let Some(hex) = self.keymap.iter().position(|&key| key == c) {
+                        self.pressed[hex] = match key.kind {
+                            KeyEventKind::Release => None,
+                            KeyEventKind::Press | KeyEventKind::Repeat => Some(Instant::now()),
+                        };
+                    }
+                }
+                // The remaining keys are frontend and debugger commands, sent on press
+                _ if key.kind == KeyEventKind::Release => {}
+                KeyCode::F(2) => commands.push(Command::QuickSave),
+                KeyCode::F(3) => commands.push(Command::QuickLoad),
+                KeyCode::Backspace => commands.push(Command::Rewind),
+                KeyCode::F(11) => commands.push(Command::Video),
+                KeyCode::F(12) => commands.push(Command::Screenshot),
+                KeyCode::F(4) => commands.push(Command::StepBack),
+                KeyCode::F(5) => commands.push(Command::Pause),
+                KeyCode::F(6) => commands.push(Command::Step),
+                KeyCode::F(7) => commands.push(Command::StepOver),
+                KeyCode::F(8) => commands.push(Command::RunToCursor),
+                KeyCode::F(9) => commands.push(Command::ToggleBreakpoint),
+                KeyCode::Up => commands.push(Command::MoveCursor(-1)),
+                KeyCode::Down => commands.push(Command::MoveCursor(1)),
+                KeyCode::PageUp => commands.push(Command::MoveCursor(-16)),
+                KeyCode::PageDown => commands.push(Command::MoveCursor(16)),
+                KeyCode::Home => commands.push(Command::CursorToPc),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Input for CrosstermInput {
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command> {
+        let mut commands = Vec::new();
+        if let Err(error) = self.read_events(&mut commands) {
+            log::error!("Failed to read terminal input: {}", error);
+        }
+        let now = Instant::now();
+        for (key, pressed) in self.pressed.iter_mut().enumerate() {
+            // Without release events, forget keys the terminal stopped repeating
+            if !self.key_releases && pressed.is_some_and(|at| now - at > self.hold_timeout) {
+                *pressed = None;
+            }
+            keypad[key] = pressed.is_some();
+        }
+        commands
+    }
+}
+
+impl Drop for CrosstermInput {
+    fn drop(&mut self) {
+        if self.key_releases {
+            // Nothing more can be done if this fails while tearing down
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+    }
+}
+
+// Polls the global keyboard state through device_query. This needs an X11
+// display on Linux and sees keys even when the terminal is not focused, but
+// gets exact press and release timing.
+#[cfg(feature = "device-query")]
+pub struct DeviceQueryInput {
+    device_state: device_query::DeviceState,
+    keys: Vec<Option<device_query::Keycode>>,   // Host key for each hex key 0x0-0xF
+    held: Vec<device_query::Keycode>,          // Keys down at the previous poll
+}
+
+#[cfg(feature = "device-query")]
+impl DeviceQueryInput {
+    pub fn new(keymap: [char; 16]) -> DeviceQueryInput {
+        DeviceQueryInput {
+            device_state: device_query::DeviceState::new(),
+            keys: keymap.iter().map(|&key| keycode(key)).collect(),
+            held: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "device-query")]
+impl Input for DeviceQueryInput {
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command> {
+        use device_query::{DeviceQuery, Keycode};
+        // Update the vector of Keycodes corresponding to keys currently being pressed
+        let pressed_keys: Vec<Keycode> = self.device_state.get_keys();
+        for (key, keycode) in self.keys.iter().enumerate() {
+            keypad[key] = keycode.as_ref().is_some_and(|keycode| pressed_keys.contains(keycode));
+        }
+        // Commands fire once when their key goes down rather than every poll,
+        // except rewind which keeps going back a frame at a time while held
+        let mut commands: Vec<Command> = pressed_keys
+            .iter()
+            .filter(|keycode| !self.held.contains(keycode))
+            .filter_map(|keycode| match keycode {
+                Keycode::Escape => Some(Command::Quit),
+                Keycode::F2 => Some(Command::QuickSave),
+                Keycode::F3 => Some(Command::QuickLoad),
+                Keycode::F4 => Some(Command::StepBack),
+                Keycode::F5 => Some(Command::Pause),
+                Keycode::F6 => Some(Command::Step),
+                Keycode::F7 => Some(Command::StepOver),
+                Keycode::F8 => Some(Command::RunToCursor),
+                Keycode::F9 => Some(Command::ToggleBreakpoint),
+                Keycode::F11 => Some(Command::Video),
+                Keycode::F12 => Some(Command::Screenshot),
+                Keycode::Up => Some(Command::MoveCursor(-1)),
+                Keycode::Down => Some(Command::MoveCursor(1)),
+                Keycode::PageUp => Some(Command::MoveCursor(-16)),
+                Keycode::PageDown => Some(Command::MoveCursor(16)),
+                Keycode::Home => Some(Command::CursorToPc),
+                _ => None,
+            })
+            .collect();
+        if pressed_keys.contains(&Keycode::Backspace) {
+            commands.push(Command::Rewind);
+        }
+        self.held = pressed_keys;
+        commands
+    }
+}
+
+// The device_query key for one of the characters in a keymap
+#[cfg(feature = "device-query")]
+fn keycode(key: char) -> Option<device_query::Keycode> {
+    use device_query::Keycode::*;
+    Some(match key.to_ascii_lowercase() {
+        '0' => Key0, '1' => Key1, '2' => Key2, '3' => Key3, '4' => Key4,
+        '5' => Key5, '6' => Key6, '7' => Key7, '8' => Key8, '9' => Key9,
+        'a' => A, 'b' => B, 'c' => C, 'd' => D, 'e' => E, 'f' => F, 'g' => G,
+        'h' => H, 'i' => I, 'j' => J, 'k' => K, 'l' => L, 'm' => M, 'n' => N,
+        'o' => O, 'p' => P, 'q' => Q, 'r' => R, 's' => S, 't' => T, 'u' => U,
+        'v' => V, 'w' => W, 'x' => X, 'y' => Y, 'z' => Z,
+        _ => return None,
+    })
+}
@@ -0,0 +1,28 @@
+// CHIP-8 Emulator written in the RUST programming language. 🦀 Praise Be Ferris 🦀
+// by Jason Zingaretti
+
+// The emulation core lives in `cpu` and has no terminal, audio or keyboard
+// dependencies, so it can be driven headless from tests and other tools.
+// `frontend` and `render` make up the terminal emulator built on top of it.
+
+pub mod asm;
+pub mod audio;
+pub mod cli;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod filter;
+pub mod frontend;
+pub mod input;
+pub mod movie;
+pub mod octo;
+pub mod palette;
+pub mod quirks;
+pub mod random;
+pub mod render;
+pub mod rewind;
+pub mod screenshot;
+pub mod snapshot;
+pub mod video;
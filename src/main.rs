@@ -1,6 +1,22 @@
-mod cpu;
-mod render;
-use cpu::{CHIP8, start_clock, run};
+use std::path::Path;
+use std::process;
+use chip_8::asm;
+use chip_8::cli::{self, Command, InputBackend, Options};
+use chip_8::cpu::{CHIP8, Config, PROGRAM_START};
+use chip_8::debugger::Debugger;
+use chip_8::disasm;
+use chip_8::error::Chip8Error;
+use chip_8::filter::Filter;
+use chip_8::frontend::{run, run_headless, Session};
+use chip_8::input::{CrosstermInput, Input};
+use chip_8::movie::{Movie, Player, Recorder};
+use chip_8::octo;
+use chip_8::random;
+use chip_8::palette::Palette;
+use chip_8::render::{ChipRender, ColorDepth};
+use chip_8::screenshot;
+use chip_8::snapshot::Snapshot;
+use chip_8::video::Video;
 extern crate log;
 
 // CHIP-8 Emulator written in the RUST programming language. 🦀 Praise Be Ferris 🦀
@@ -11,10 +27,264 @@ extern crate log;
 // https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#fetch
 
 fn main(){
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) => *options,
+        Ok(Command::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Command::Disasm { rom, platform }) => {
+            disassemble(&rom, &platform);
+            return;
+        }
+        Ok(Command::Asm { source, output }) => {
+            let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+            assemble(&source, &output);
+            return;
+        }
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    // A movie brings the settings it was recorded with
+    let movie = options.play.as_ref().map(|path| match Movie::load(path) {
+        Ok(movie) => movie,
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    });
+    let config = match &movie {
+        Some(movie) => movie.config,
+        None => {
+            // The platform name was already checked against the presets when parsing
+            let preset = Config::preset(&options.platform).unwrap();
+            Config {
+                ips: options.ips,
+                // A recording needs a known seed to play back the same way
+                seed: options.seed.or_else(|| options.record.is_some().then(random::entropy)),
+                ..preset
+            }
+        }
+    };
+    let mut chip = CHIP8::with_config(config);
+    if let Err(error) = load(&mut chip, &options.rom) {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
+    if let (Some(movie), Some(path)) = (&movie, &options.play) {
+        if let Err(error) = movie.check_rom(&chip) {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+    if let Some(path) = &options.load_state {
+        match Snapshot::load(path) {
+            Ok(snapshot) => chip.restore(&snapshot),
+            Err(error) => {
+                eprintln!("{}: {}", path.display(), error);
+                process::exit(1);
+            }
+        }
+    }
+    // Sound does not change what the program does, so the command line wins
+    // over a movie's or save state's settings
+    let config = &mut chip.config;
+    config.beep_frequency = options.tone.unwrap_or(config.beep_frequency);
+    config.waveform = options.waveform.unwrap_or(config.waveform);
+    config.volume = options.volume.unwrap_or(config.volume);
+    config.mute |= options.mute;
+
+    // Settings given on the command line win over ones kept next to the ROM
+    let palette = options.palette.or_else(|| rom_setting(&options.rom, "palette", |path| Palette::load(path))).unwrap_or_default();
+    let mut video = options.video.as_ref().map(|path| match Video::create(path, options.image_scale, &palette) {
+        Ok(video) => video,
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    });
+
+    if options.headless {
+        let record = |chip: &CHIP8| video.as_mut().map_or(Ok(()), |video| video.frame(&chip.display));
+        let result = match &movie {
+            Some(movie) => movie.play(&mut chip, record).map(Some),
+            None => run_headless(&mut chip, options.frames.unwrap_or(0), record).map(|()| None),
+        };
+        let matched = match result {
+            Ok(matched) => matched,
+            Err(error) => {
+                eprintln!("CHIP-8 halted: {}", error);
+                process::exit(1);
+            }
+        };
+        if let (Some(video), Some(path)) = (video.as_mut(), &options.video) {
+            if let Err(error) = video.finish() {
+                eprintln!("{}: {}", path.display(), error);
+                process::exit(1);
+            }
+        }
+        // Saved before a mismatched movie fails the process, so it can be
+        // compared with the recording
+        if let Some(path) = &options.screenshot {
+            if let Err(error) = screenshot::save(&chip.display, path, options.image_scale, &palette) {
+                eprintln!("{}: {}", path.display(), error);
+                process::exit(1);
+            }
+        }
+        if let Some(matched) = matched {
+            print!("{}", chip.display);
+            report_playback(true, matched);
+        }
+        return;
+    }
+
     tui_logger::init_logger(log::LevelFilter::Trace).unwrap();
     tui_logger::set_default_level(log::LevelFilter::Trace);
-    let chip = CHIP8::new();
-    start_clock(&chip);
-    chip.lock().unwrap().load_program(String::from("./ROMS/Test.ch8"));
-    run(&chip);
-}
\ No newline at end of file
+    // The input backend is dropped before the renderer restores the terminal,
+    // and both before any error is printed.
+    let (result, recorded, played) = {
+        let depth = options.colors.unwrap_or_else(ColorDepth::detect);
+        let filter = options.filter.or_else(|| rom_setting(&options.rom, "filter", |path| Filter::load(path))).unwrap_or(Filter::Off);
+        let mut renderer = ChipRender::setup(options.render, options.scale, &palette, depth, filter)
+            .expect("Failed to initialize chip display renderer");
+        let mut input = input_backend(&options);
+        let mut debugger = (options.debug || !options.breakpoints.is_empty())
+            .then(|| Debugger::new(options.debug, options.breakpoints.iter().copied()));
+        let session = Session {
+            state_path: options.rom.with_extension("state"),
+            rewind_frames: options.rewind as usize * 60,
+            capture_path: options.rom.with_extension(""),
+            image_scale: options.image_scale,
+            palette,
+        };
+        // Movies sit between the keyboard and the emulator
+        let mut recorder = None;
+        let mut player = None;
+        let input: &mut dyn Input = if options.record.is_some() {
+            recorder.insert(Recorder::new(input.as_mut(), Movie::new(&chip)))
+        } else if let Some(movie) = movie {
+            player.insert(Player::new(input.as_mut(), movie))
+        } else {
+            input.as_mut()
+        };
+        let result = run(&mut chip, &mut renderer, input, &session, debugger.as_mut(), video);
+        let recorded = recorder.map(|recorder| recorder.finish(&chip.display));
+        let played = player.map(|player| (player.finished(), player.movie.verify(&chip.display)));
+        (result, recorded, played)
+    };
+    if let Err(error) = result {
+        eprintln!("CHIP-8 halted: {}", error);
+        process::exit(1);
+    }
+    if let (Some(movie), Some(path)) = (recorded, &options.record) {
+        if let Err(error) = movie.save(path) {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+        println!("Recorded {} frames to {}", movie.frames.len(), path.display());
+    }
+    if let Some((finished, matched)) = played {
+        report_playback(finished, matched);
+    }
+}
+
+// A setting kept in a file named after the ROM with the given extension,
+// such as `Breakout.palette`, if there is one
+fn rom_setting<T, F: Fn(&Path) -> Result<T, Chip8Error>>(rom: &Path, extension: &str, load: F) -> Option<T> {
+    let path = rom.with_extension(extension);
+    if !path.exists() {
+        return None;
+    }
+    match load(&path) {
+        Ok(setting) => Some(setting),
+        Err(error) => {
+            eprintln!("{}: {}", path.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+// Tells the user whether a movie reproduced its recording, failing the
+// process if it did not
+fn report_playback(finished: bool, matched: bool) {
+    if !finished {
+        println!("Movie stopped before its last frame");
+    } else if matched {
+        println!("Movie played back: the final display matches the recording");
+    } else {
+        eprintln!("Movie played back: the final display differs from the recording");
+        process::exit(1);
+    }
+}
+
+// Prints the assembly for a ROM file as it would be loaded into memory
+fn disassemble(rom: &Path, platform: &str) {
+    let mut chip = CHIP8::with_config(Config::preset(platform).unwrap());
+    let program = std::fs::read(rom).map_err(Chip8Error::from).and_then(|program| {
+        chip.load_rom(&program)?;
+        Ok(program)
+    });
+    match program {
+        Ok(program) => {
+            let start = PROGRAM_START as usize;
+            print!("{}", disasm::disassemble(&chip.memory, start..start + program.len(), chip.config.platform));
+        }
+        Err(error) => {
+            eprintln!("{}: {}", rom.display(), error);
+            process::exit(1);
+        }
+    }
+}
+
+// Octo source files are compiled as they are loaded, anything else is
+// loaded as a ROM image
+fn load(chip: &mut CHIP8, path: &Path) -> Result<(), String> {
+    let loaded = if is_octo(path) {
+        let source = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+        let rom = octo::compile(&source, path).map_err(|error| error.to_string())?;
+        chip.load_rom(&rom)
+    } else {
+        chip.load_program(path)
+    };
+    loaded.map_err(|error| format!("{}: {}", path.display(), error))
+}
+
+fn is_octo(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "8o")
+}
+
+// Assembles or compiles a source file and writes the ROM to `output`
+fn assemble(source: &Path, output: &Path) {
+    let text = match std::fs::read_to_string(source) {
+        Ok(text) => text,
+        Err(error) => {
+            eprintln!("{}: {}", source.display(), error);
+            process::exit(1);
+        }
+    };
+    let compiled = if is_octo(source) { octo::compile(&text, source) } else { asm::assemble(&text, source) };
+    let rom = match compiled {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+    if let Err(error) = std::fs::write(output, &rom) {
+        eprintln!("{}: {}", output.display(), error);
+        process::exit(1);
+    }
+}
+
+fn input_backend(options: &Options) -> Box<dyn Input> {
+    match options.input {
+        InputBackend::Terminal => Box::new(CrosstermInput::new(options.keymap, options.hold_timeout)),
+        #[cfg(feature = "device-query")]
+        InputBackend::DeviceQuery => Box::new(chip_8::input::DeviceQueryInput::new(options.keymap)),
+        #[cfg(not(feature = "device-query"))]
+        InputBackend::DeviceQuery => unreachable!("rejected by the command-line parser"),
+    }
+}
@@ -0,0 +1,226 @@
+use std::path::Path;
+use crate::cpu::{CHIP8, Config};
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::input::{Command, Input};
+use crate::snapshot::{read_config, write_config, Reader};
+
+// Input movies: the keypad state of every frame of a session, recorded from
+// the moment a ROM was loaded. The emulator is deterministic once its seed is
+// fixed, so playing the keys back reproduces the session exactly, and the
+// hash of the final framebuffer stored at the end confirms that it did.
+//
+// The file starts with a 4 byte magic number and a 16-bit format version,
+// then a hash of the memory as loaded, the settings the session ran with
+// (stored as in save states), the frame count, one 16-bit key mask per frame
+// with bit N set while hex key N was held, and the framebuffer hash. Numbers
+// are little-endian.
+
+const MAGIC: &[u8; 4] = b"CH8M";
+const VERSION: u16 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,         // Hash of memory right after the ROM was loaded
+    pub config: Config,        // Settings the session ran with, always with a seed
+    pub frames: Vec<u16>,      // Hex keys held during each frame
+    pub display_hash: u64,     // Hash of the framebuffer after the last frame
+}
+
+impl Movie {
+    // An empty movie for a machine that has just loaded its ROM. Its config
+    // needs a seed for the random numbers to play back the same way.
+    pub fn new(chip8: &CHIP8) -> Movie {
+        Movie { rom_hash: rom_hash(chip8), config: chip8.config, frames: Vec::new(), display_hash: 0 }
+    }
+
+    // Makes sure `chip8` is running the ROM the movie was recorded with
+    pub fn check_rom(&self, chip8: &CHIP8) -> Result<(), Chip8Error> {
+        if rom_hash(chip8) != self.rom_hash {
+            return Err(Chip8Error::InvalidMovie(String::from("it was recorded with a different ROM")));
+        }
+        Ok(())
+    }
+
+    // Whether the framebuffer matches the one the recording ended on
+    pub fn verify(&self, display: &Display) -> bool {
+        display_hash(display) == self.display_hash
+    }
+
+    // Plays every frame as fast as possible, stopping early if the program
+    // exits, and returns whether the final framebuffer matched. `after_frame`
+    // sees the machine after each frame, e.g. to record a video.
+    pub fn play<F>(&self, chip8: &mut CHIP8, mut after_frame: F) -> Result<bool, Chip8Error>
+    where
+        F: FnMut(&CHIP8) -> Result<(), Chip8Error>,
+    {
+        for &keys in &self.frames {
+            chip8.keypad = keypad(keys);
+            let run = chip8.run_frame()?;
+            after_frame(chip8)?;
+            if run.exited {
+                break;
+            }
+        }
+        Ok(self.verify(&chip8.display))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        write_config(&mut out, &self.config);
+        out.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        for keys in &self.frames {
+            out.extend_from_slice(&keys.to_le_bytes());
+        }
+        out.extend_from_slice(&self.display_hash.to_le_bytes());
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, Chip8Error> {
+        Movie::read(&mut Reader { bytes }).map_err(|error| match error {
+            // The reader shared with save states reports problems as those
+            Chip8Error::InvalidSnapshot(reason) => Chip8Error::InvalidMovie(reason),
+            error => error,
+        })
+    }
+
+    fn read(reader: &mut Reader) -> Result<Movie, Chip8Error> {
+        if reader.take(4)? != MAGIC {
+            return Err(Chip8Error::InvalidMovie(String::from("not a movie")));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(Chip8Error::InvalidMovie(format!("unsupported movie version {}", version)));
+        }
+        let rom_hash = reader.u64()?;
+        let config = read_config(reader)?;
+        let count = reader.u32()? as usize;
+        let frames = (0..count).map(|_| reader.u16()).collect::<Result<Vec<u16>, Chip8Error>>()?;
+        let display_hash = reader.u64()?;
+        if !reader.bytes.is_empty() {
+            return Err(Chip8Error::InvalidMovie(String::from("unexpected data at the end")));
+        }
+        Ok(Movie { rom_hash, config, frames, display_hash })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Movie, Chip8Error> {
+        Movie::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Records the keys of every frame while passing input through from another
+// backend. Rewinding and loading states would break the timeline, so those
+// commands are dropped.
+pub struct Recorder<'a> {
+    input: &'a mut dyn Input,
+    pub movie: Movie,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(input: &'a mut dyn Input, movie: Movie) -> Recorder<'a> {
+        Recorder { input, movie }
+    }
+
+    // Stores the final framebuffer hash once the session is over
+    pub fn finish(mut self, display: &Display) -> Movie {
+        self.movie.display_hash = display_hash(display);
+        self.movie
+    }
+}
+
+impl Input for Recorder<'_> {
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command> {
+        let commands = timeline_commands(self.input.poll(keypad));
+        // The frame after a quit never runs
+        if !commands.contains(&Command::Quit) {
+            self.movie.frames.push(key_mask(keypad));
+        }
+        commands
+    }
+}
+
+// Feeds the keys from a movie into the keypad frame by frame, asking the
+// frontend to quit once they run out. Host keys only issue commands.
+pub struct Player<'a> {
+    input: &'a mut dyn Input,
+    pub movie: Movie,
+    frame: usize,    // Index of the next frame to play
+}
+
+impl<'a> Player<'a> {
+    pub fn new(input: &'a mut dyn Input, movie: Movie) -> Player<'a> {
+        Player { input, movie, frame: 0 }
+    }
+
+    // Whether every frame of the movie has been played
+    pub fn finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+impl Input for Player<'_> {
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command> {
+        let mut host_keys = [false; 16];
+        let mut commands = timeline_commands(self.input.poll(&mut host_keys));
+        match self.movie.frames.get(self.frame) {
+            Some(&keys) => {
+                *keypad = self::keypad(keys);
+                self.frame += 1;
+            }
+            None => commands.push(Command::Quit),
+        }
+        commands
+    }
+}
+
+// Drops the commands that would jump to another point in time
+fn timeline_commands(commands: Vec<Command>) -> Vec<Command> {
+    commands
+        .into_iter()
+        .filter(|&command| {
+            let jumps = matches!(command, Command::Rewind | Command::QuickLoad);
+            if jumps {
+                log::warn!("Rewinding and loading states are disabled while a movie records or plays.");
+            }
+            !jumps
+        })
+        .collect()
+}
+
+fn key_mask(keypad: &[bool; 16]) -> u16 {
+    keypad.iter().enumerate().fold(0, |mask, (key, &held)| mask | ((held as u16) << key))
+}
+
+fn keypad(mask: u16) -> [bool; 16] {
+    std::array::from_fn(|key| mask & (1 << key) != 0)
+}
+
+// Identifies the loaded program by hashing all of memory, which also covers
+// the fonts and the memory size of the platform
+pub fn rom_hash(chip8: &CHIP8) -> u64 {
+    fnv1a(&chip8.memory)
+}
+
+// Identifies a picture, including its resolution
+pub fn display_hash(display: &Display) -> u64 {
+    let mut bytes = Vec::with_capacity(display.width() * display.height() + 4);
+    bytes.extend_from_slice(&(display.width() as u16).to_le_bytes());
+    bytes.extend_from_slice(&(display.height() as u16).to_le_bytes());
+    for row in display.rows() {
+        bytes.extend_from_slice(row);
+    }
+    fnv1a(&bytes)
+}
+
+// 64-bit FNV-1a, which is plenty to tell programs and pictures apart
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3))
+}
@@ -0,0 +1,849 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use crate::cpu::PROGRAM_START;
+use crate::error::AsmError;
+
+// Compiles Octo, the high-level assembly language most modern CHIP-8,
+// SUPER-CHIP and XO-CHIP homebrew is written in, straight into a ROM image.
+//
+// Supported: `: label`, `:const`, `:alias`, `:calc`, `:macro`, `:unpack`,
+// `:next`, `:org`, `:byte`, `:pointer`, `:call` and `:proto`, every
+// statement form (`v0 := 5`, `i += v2`, `sprite v0 v1 5`, ...),
+// `if ... then`, `if ... begin ... else ... end`, `loop ... while ... again`
+// and the `<`, `>`, `<=` and `>=` comparisons, which use VF as scratch.
+// `:breakpoint` and `:monitor` are accepted and ignored. `:stringmode` and
+// `:assert` are not supported.
+//
+// Execution starts at the `main` label. Unless `: main` begins the program a
+// jump to it is placed at 0x200.
+
+// Technical References:
+// https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/Manual.md
+// https://github.com/JohnEarnest/Octo/blob/gh-pages/docs/XO-ChipSpecification.md
+
+// Macro expansions allowed while compiling one program, to stop a macro that
+// invokes itself from running forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A reference to a label that was not defined yet when it was used
+struct Fixup {
+    addr: usize,   // Address of the instruction or data to patch
+    kind: FixupKind,
+    name: String,
+    line: usize,
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    Addr12,          // The low 12 bits of an instruction word
+    Addr16,          // A whole 16-bit word (`:pointer`, `i := long`)
+    UnpackNibble,    // The low nibble of `v0 := NN` from `:unpack N name`
+    UnpackHigh,      // The byte of `v0 := NN` from `:unpack long name`
+    UnpackLow,       // The byte of `v1 := NN` from `:unpack`
+}
+
+// A value that is either known now or names a label defined further on
+enum Value {
+    Known(i64),
+    Label(String),
+}
+
+// A condition compiled to the instructions it needs, ending with one that
+// skips the next instruction when the condition is false
+struct Condition {
+    setup: Vec<u16>,
+    skip_unless: u16,
+}
+
+// An open `if ... begin` block, or `loop` waiting for its `again`
+enum Block {
+    If { jump: usize },                       // The jump over the block, patched at `else` or `end`
+    Else { jump: usize },                     // The jump over the `else` part, patched at `end`
+    Loop { start: usize, breaks: Vec<usize> }, // The jumps out of the loop made by `while`
+}
+
+// Compiles Octo source into a ROM image to be loaded at 0x200. `path` names
+// the file in error messages.
+pub fn compile(source: &str, path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut compiler = Compiler {
+        file: path.display().to_string(),
+        tokens: tokenize(source),
+        line: 1,
+        rom: Vec::new(),
+        here: PROGRAM_START as usize,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        next: None,
+        expansions: 0,
+    };
+    compiler.compile()?;
+    Ok(compiler.rom)
+}
+
+// Splits source into whitespace separated tokens, dropping `#` comments
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (number, line) in source.lines().enumerate() {
+        for text in line.split_whitespace() {
+            if text.starts_with('#') {
+                break;
+            }
+            tokens.push_back(Token { text: text.to_string(), line: number + 1 });
+        }
+    }
+    tokens
+}
+
+struct Compiler {
+    file: String,
+    tokens: VecDeque<Token>,
+    line: usize,                        // Line of the last token taken, for errors
+    rom: Vec<u8>,                       // Everything compiled so far, starting at 0x200
+    here: usize,                        // Address the next byte goes to
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u16>,      // Register names given by `:alias`
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    next: Option<String>,               // Label for the second byte of the next instruction
+    expansions: usize,
+}
+
+impl Compiler {
+    fn error(&self, message: String) -> AsmError {
+        AsmError { file: self.file.clone(), line: self.line, message }
+    }
+
+    fn next_token(&mut self) -> Result<String, AsmError> {
+        let token = self.tokens.pop_front().ok_or_else(|| self.error(String::from("unexpected end of program")))?;
+        self.line = token.line;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let token = self.next_token()?;
+        if token != expected {
+            return Err(self.error(format!("expected '{}' but found '{}'", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<(), AsmError> {
+        // Leaving room for the jump to main unless main comes first
+        let main_first = self.tokens.len() >= 2 && self.tokens[0].text == ":" && self.tokens[1].text == "main";
+        if !main_first {
+            self.fixups.push(Fixup { addr: self.here, kind: FixupKind::Addr12, name: String::from("main"), line: 1 });
+            self.emit_word(0x1000)?;
+        }
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.blocks.is_empty() {
+            return Err(self.error(String::from("missing 'end' or 'again' at the end of the program")));
+        }
+        if !self.labels.contains_key("main") {
+            return Err(self.error(String::from("the program has no 'main' label")));
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let target = *self.labels.get(&fixup.name).ok_or_else(|| self.error(format!("undefined name '{}'", fixup.name)))?;
+            let at = fixup.addr - PROGRAM_START as usize;
+            match fixup.kind {
+                FixupKind::Addr12 => {
+                    if target > 0xFFF {
+                        return Err(self.error(format!("'{}' is beyond the 4KB a 12-bit address reaches", fixup.name)));
+                    }
+                    self.rom[at] |= (target >> 8) as u8;
+                    self.rom[at + 1] = target as u8;
+                }
+                FixupKind::Addr16 => {
+                    self.rom[at] = (target >> 8) as u8;
+                    self.rom[at + 1] = target as u8;
+                }
+                FixupKind::UnpackNibble => self.rom[at + 1] |= (target >> 8) as u8 & 0xF,
+                FixupKind::UnpackHigh => self.rom[at + 1] = (target >> 8) as u8,
+                FixupKind::UnpackLow => self.rom[at + 1] = target as u8,
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next_token()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.insert(name.clone(), self.here).is_some() {
+                    return Err(self.error(format!("'{}' is already defined", name)));
+                }
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.known()?;
+                self.constants.insert(name, value as f64);
+            }
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc_block()?;
+                self.constants.insert(name, value);
+            }
+            ":byte" => {
+                let value = self.byte()?;
+                self.emit_byte(value)?;
+            }
+            ":pointer" => {
+                let value = self.value()?;
+                self.emit_address(0, value, FixupKind::Addr16)?;
+            }
+            ":org" => {
+                let addr = self.known()?;
+                if !(PROGRAM_START as i64..=0xFFFF).contains(&addr) {
+                    return Err(self.error(format!(":org address {:#X} is outside program memory", addr)));
+                }
+                self.here = addr as usize;
+            }
+            ":next" => self.next = Some(self.name()?),
+            ":unpack" => {
+                // :unpack N name sets v0 to N and the top 4 bits of the address
+                // and v1 to the rest; :unpack long name splits all 16 bits
+                let long = self.peek() == Some("long");
+                let (high, kind) = if long {
+                    self.next_token()?;
+                    (0x6000, FixupKind::UnpackHigh)
+                } else {
+                    (0x6000 | ((self.nibble()? as u16) << 4), FixupKind::UnpackNibble)
+                };
+                let value = self.value()?;
+                self.unpack(high, value, kind)?;
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let value = self.value()?;
+                self.emit_address(0x2000, value, FixupKind::Addr12)?;
+            }
+            ":proto" | ":breakpoint" => {
+                self.next_token()?;
+            }
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token()?;
+            }
+            "{" => return Err(self.error(String::from("unexpected '{'"))),
+            _ => return self.instruction(token),
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, token: String) -> Result<(), AsmError> {
+        let word = match token.as_str() {
+            "clear" => 0x00E0,
+            "return" | ";" => 0x00EE,
+            "exit" => 0x00FD,
+            "lores" => 0x00FE,
+            "hires" => 0x00FF,
+            "scroll-down" => 0x00C0 | self.nibble()? as u16,
+            "scroll-up" => 0x00D0 | self.nibble()? as u16,
+            "scroll-right" => 0x00FB,
+            "scroll-left" => 0x00FC,
+            "audio" => 0xF002,
+            "plane" => 0xF001 | (self.nibble()? as u16) << 8,
+            "bcd" => 0xF033 | self.register()? << 8,
+            "saveflags" => 0xF075 | self.register()? << 8,
+            "loadflags" => 0xF085 | self.register()? << 8,
+            "save" | "load" => {
+                let x = self.register()?;
+                let load = token == "load";
+                if self.peek() == Some("-") {
+                    // XO-CHIP save/load vx - vy
+                    self.next_token()?;
+                    let y = self.register()?;
+                    0x5002 | x << 8 | y << 4 | load as u16
+                } else {
+                    (if load { 0xF065 } else { 0xF055 }) | x << 8
+                }
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                0xD000 | x << 8 | y << 4 | self.nibble()? as u16
+            }
+            "jump" | "jump0" | "native" => {
+                let opcode = match token.as_str() {
+                    "jump" => 0x1000,
+                    "jump0" => 0xB000,
+                    _ => 0x0000,
+                };
+                let value = self.value()?;
+                return self.emit_address(opcode, value, FixupKind::Addr12);
+            }
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.here, breaks: Vec::new() });
+                return Ok(());
+            }
+            "while" => {
+                let condition = self.condition()?;
+                let jump = self.jump_unless(condition)?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(self.error(String::from("'while' outside a loop"))),
+                }
+                return Ok(());
+            }
+            "again" => {
+                let Some(Block::Loop { start, breaks }) = self.blocks.pop() else {
+                    return Err(self.error(String::from("'again' without a matching 'loop'")));
+                };
+                let word = self.jump_to(start)?;
+                self.emit_word(word)?;
+                for jump in breaks {
+                    self.patch_jump(jump)?;
+                }
+                return Ok(());
+            }
+            "if" => {
+                let condition = self.condition()?;
+                match self.next_token()?.as_str() {
+                    "then" => {
+                        for word in condition.setup {
+                            self.emit_word(word)?;
+                        }
+                        self.emit_word(condition.skip_unless)?;
+                    }
+                    "begin" => {
+                        let jump = self.jump_unless(condition)?;
+                        self.blocks.push(Block::If { jump });
+                    }
+                    other => return Err(self.error(format!("expected 'then' or 'begin' but found '{}'", other))),
+                }
+                return Ok(());
+            }
+            "else" => {
+                let Some(Block::If { jump }) = self.blocks.pop() else {
+                    return Err(self.error(String::from("'else' without a matching 'if ... begin'")));
+                };
+                let skip = self.here;
+                self.emit_word(0x1000)?;
+                self.patch_jump(jump)?;
+                self.blocks.push(Block::Else { jump: skip });
+                return Ok(());
+            }
+            "end" => {
+                match self.blocks.pop() {
+                    Some(Block::If { jump } | Block::Else { jump }) => self.patch_jump(jump)?,
+                    _ => return Err(self.error(String::from("'end' without a matching 'if ... begin'"))),
+                }
+                return Ok(());
+            }
+            "i" => {
+                let op = self.next_token()?;
+                match (op.as_str(), self.peek()) {
+                    (":=", Some("hex")) => {
+                        self.next_token()?;
+                        0xF029 | self.register()? << 8
+                    }
+                    (":=", Some("bighex")) => {
+                        self.next_token()?;
+                        0xF030 | self.register()? << 8
+                    }
+                    (":=", Some("long")) => {
+                        self.next_token()?;
+                        let value = self.value()?;
+                        self.emit_word(0xF000)?;
+                        return self.emit_address(0, value, FixupKind::Addr16);
+                    }
+                    (":=", _) => {
+                        let value = self.value()?;
+                        return self.emit_address(0xA000, value, FixupKind::Addr12);
+                    }
+                    ("+=", _) => 0xF01E | self.register()? << 8,
+                    _ => return Err(self.error(format!("unknown operator 'i {}'", op))),
+                }
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                match token.as_str() {
+                    "delay" => 0xF015 | x << 8,
+                    "buzzer" => 0xF018 | x << 8,
+                    _ => 0xF03A | x << 8,
+                }
+            }
+            _ => {
+                if let Some(x) = self.register_named(&token) {
+                    return self.assignment(x);
+                }
+                if self.macros.contains_key(&token) {
+                    return self.expand_macro(&token);
+                }
+                // Numbers and constants on their own are data bytes, while
+                // any other name calls the subroutine at that label
+                if let Some(value) = self.number(&token) {
+                    return self.emit_data(value);
+                }
+                if let Some(&value) = self.constants.get(&token) {
+                    return self.emit_data(value as i64);
+                }
+                let value = self.label_value(token)?;
+                return self.emit_address(0x2000, value, FixupKind::Addr12);
+            }
+        };
+        self.emit_word(word)
+    }
+
+    // `vx op ...` statements
+    fn assignment(&mut self, x: u16) -> Result<(), AsmError> {
+        let op = self.next_token()?;
+        let operand = self.peek().map(str::to_string).unwrap_or_default();
+        let y = self.register_named(&operand);
+        let word = match (op.as_str(), y) {
+            (":=", Some(y)) => 0x8000 | y << 4,
+            ("|=", Some(y)) => 0x8001 | y << 4,
+            ("&=", Some(y)) => 0x8002 | y << 4,
+            ("^=", Some(y)) => 0x8003 | y << 4,
+            ("+=", Some(y)) => 0x8004 | y << 4,
+            ("-=", Some(y)) => 0x8005 | y << 4,
+            (">>=", Some(y)) => 0x8006 | y << 4,
+            ("=-", Some(y)) => 0x8007 | y << 4,
+            ("<<=", Some(y)) => 0x800E | y << 4,
+            (":=", None) => match operand.as_str() {
+                "random" => {
+                    self.next_token()?;
+                    0xC000 | self.byte()? as u16
+                }
+                "key" => {
+                    self.next_token()?;
+                    0xF00A
+                }
+                "delay" => {
+                    self.next_token()?;
+                    0xF007
+                }
+                _ => 0x6000 | self.byte()? as u16,
+            },
+            ("+=", None) => 0x7000 | self.byte()? as u16,
+            ("-=", None) => 0x7000 | (self.byte()? as u16).wrapping_neg() & 0xFF,
+            _ => return Err(self.error(format!("unknown operator '{}' for a register", op))),
+        };
+        if y.is_some() {
+            self.next_token()?;
+        }
+        self.emit_word(word | x << 8)
+    }
+
+    // Parses `vx op operand` after `if` or `while`
+    fn condition(&mut self) -> Result<Condition, AsmError> {
+        let x = self.register()?;
+        let op = self.next_token()?;
+        match op.as_str() {
+            "key" => return Ok(Condition { setup: Vec::new(), skip_unless: 0xE0A1 | x << 8 }),
+            "-key" => return Ok(Condition { setup: Vec::new(), skip_unless: 0xE09E | x << 8 }),
+            _ => {}
+        }
+        let operand = self.peek().map(str::to_string).unwrap_or_default();
+        let y = self.register_named(&operand);
+        if y.is_some() {
+            self.next_token()?;
+        }
+        let condition = match (op.as_str(), y) {
+            ("==", Some(y)) => Condition { setup: Vec::new(), skip_unless: 0x9000 | x << 8 | y << 4 },
+            ("!=", Some(y)) => Condition { setup: Vec::new(), skip_unless: 0x5000 | x << 8 | y << 4 },
+            ("==", None) => Condition { setup: Vec::new(), skip_unless: 0x4000 | x << 8 | self.byte()? as u16 },
+            ("!=", None) => Condition { setup: Vec::new(), skip_unless: 0x3000 | x << 8 | self.byte()? as u16 },
+            ("<" | ">" | "<=" | ">=", y) => {
+                // Subtracting one side from the other in VF leaves the no-borrow
+                // flag there: 1 when the left side of the subtraction is larger or equal
+                let load = match y {
+                    Some(y) => 0x8F00 | y << 4,
+                    None => 0x6F00 | self.byte()? as u16,
+                };
+                let (subtract, flag_when_true) = match op.as_str() {
+                    ">=" => (0x8F07 | x << 4, 1),   // vf := y; vf =- vx, so vx >= y
+                    "<" => (0x8F07 | x << 4, 0),
+                    "<=" => (0x8F05 | x << 4, 1),   // vf := y; vf -= vx, so y >= vx
+                    _ => (0x8F05 | x << 4, 0),
+                };
+                Condition { setup: vec![load, subtract], skip_unless: 0x4F00 | flag_when_true }
+            }
+            _ => return Err(self.error(format!("unknown comparison '{}'", op))),
+        };
+        Ok(condition)
+    }
+
+    // Emits a condition that jumps away when it is false, returning the
+    // address of the jump so it can be pointed at the right place later
+    fn jump_unless(&mut self, condition: Condition) -> Result<usize, AsmError> {
+        for word in condition.setup {
+            self.emit_word(word)?;
+        }
+        // The opposite skip steps over the jump when the condition holds
+        let skip_if = match condition.skip_unless & 0xF000 {
+            0x3000 => condition.skip_unless + 0x1000,
+            0x4000 => condition.skip_unless - 0x1000,
+            0x5000 => condition.skip_unless + 0x4000,
+            0x9000 => condition.skip_unless - 0x4000,
+            _ if condition.skip_unless & 0xFF == 0xA1 => condition.skip_unless & 0xFF00 | 0x9E,
+            _ => condition.skip_unless & 0xFF00 | 0xA1,
+        };
+        self.emit_word(skip_if)?;
+        let jump = self.here;
+        self.emit_word(0x1000)?;
+        Ok(jump)
+    }
+
+    // Points the jump at `addr` to the current address
+    fn patch_jump(&mut self, addr: usize) -> Result<(), AsmError> {
+        let at = addr - PROGRAM_START as usize;
+        let word = self.jump_to(self.here)?;
+        self.rom[at..at + 2].copy_from_slice(&word.to_be_bytes());
+        Ok(())
+    }
+
+    // A jump to `target`, which has to be within a 12-bit address's reach
+    fn jump_to(&self, target: usize) -> Result<u16, AsmError> {
+        if target > 0xFFF {
+            return Err(self.error(format!("jump target {:#X} is beyond the 4KB a 12-bit address reaches", target)));
+        }
+        Ok(0x1000 | target as u16)
+    }
+
+    fn unpack(&mut self, high: u16, value: Value, kind: FixupKind) -> Result<(), AsmError> {
+        match value {
+            Value::Known(addr) => {
+                let high = match kind {
+                    FixupKind::UnpackHigh => high | (addr as u16 >> 8),
+                    _ => high | (addr as u16 >> 8 & 0xF),
+                };
+                self.emit_word(high)?;
+                self.emit_word(0x6100 | (addr as u16 & 0xFF))
+            }
+            Value::Label(name) => {
+                let line = self.line;
+                self.fixups.push(Fixup { addr: self.here, kind, name: name.clone(), line });
+                self.emit_word(high)?;
+                self.fixups.push(Fixup { addr: self.here, kind: FixupKind::UnpackLow, name, line });
+                self.emit_word(0x6100)
+            }
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let line = self.tokens.front().map_or(self.line, |token| token.line);
+            let text = self.next_token()?;
+            match text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(Token { text, line });
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // Replaces a macro invocation with its body, substituting the arguments
+    fn expand_macro(&mut self, name: &str) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(format!("macro '{}' expands forever", name)));
+        }
+        let count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for i in 0..count {
+            let arg = self.next_token()?;
+            args.insert(self.macros[name].params[i].clone(), arg);
+        }
+        let body = &self.macros[name].body;
+        for token in body.iter().rev() {
+            let text = args.get(&token.text).cloned().unwrap_or_else(|| token.text.clone());
+            self.tokens.push_front(Token { text, line: token.line });
+        }
+        Ok(())
+    }
+
+    // A name being defined, which must not be a keyword or register
+    fn name(&mut self) -> Result<String, AsmError> {
+        let name = self.next_token()?;
+        if self.register_named(&name).is_some() || self.number(&name).is_some() || name.starts_with([':', '{', '}']) {
+            return Err(self.error(format!("'{}' cannot be used as a name", name)));
+        }
+        Ok(name)
+    }
+
+    fn register(&mut self) -> Result<u16, AsmError> {
+        let token = self.next_token()?;
+        self.register_named(&token).ok_or_else(|| self.error(format!("expected a register but found '{}'", token)))
+    }
+
+    // v0-vF, in either case, or an alias for one
+    fn register_named(&self, token: &str) -> Option<u16> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let digit = token.strip_prefix(['v', 'V']).filter(|digit| digit.len() == 1)?;
+        u16::from_str_radix(digit, 16).ok()
+    }
+
+    fn number(&self, token: &str) -> Option<i64> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+            i64::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+            i64::from_str_radix(binary, 2).ok()?
+        } else {
+            digits.parse().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    // A number, constant, `{ calc }` block or label, which may not be defined yet
+    fn value(&mut self) -> Result<Value, AsmError> {
+        if self.peek() == Some("{") {
+            return Ok(Value::Known(self.calc_block()? as i64));
+        }
+        let token = self.next_token()?;
+        if let Some(value) = self.number(&token) {
+            return Ok(Value::Known(value));
+        }
+        if let Some(&value) = self.constants.get(&token) {
+            return Ok(Value::Known(value as i64));
+        }
+        self.label_value(token)
+    }
+
+    fn label_value(&self, name: String) -> Result<Value, AsmError> {
+        if self.register_named(&name).is_some() || name.starts_with([':', '{', '}']) {
+            return Err(self.error(format!("expected a value but found '{}'", name)));
+        }
+        Ok(match self.labels.get(&name) {
+            Some(&addr) => Value::Known(addr as i64),
+            None => Value::Label(name),
+        })
+    }
+
+    // A value that has to be known at this point in the program
+    fn known(&mut self) -> Result<i64, AsmError> {
+        match self.value()? {
+            Value::Known(value) => Ok(value),
+            Value::Label(name) => Err(self.error(format!("undefined name '{}'", name))),
+        }
+    }
+
+    // An 8-bit value, where negative numbers count down from 256
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        let value = self.known()?;
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(format!("value {} does not fit in a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        let value = self.known()?;
+        if !(0..=15).contains(&value) {
+            return Err(self.error(format!("value {} does not fit in 4 bits", value)));
+        }
+        Ok(value as u8)
+    }
+
+    // Evaluates `{ ... }` with the rules of Octo's `:calc`: whitespace separated
+    // tokens, all operators with the same precedence, evaluated right to left
+    fn calc_block(&mut self) -> Result<f64, AsmError> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            if token == "}" {
+                break;
+            }
+            tokens.push(token);
+        }
+        let mut position = 0;
+        let value = self.calc(&tokens, &mut position)?;
+        if position != tokens.len() {
+            return Err(self.error(format!("unexpected '{}' in calculation", tokens[position])));
+        }
+        Ok(value)
+    }
+
+    fn calc(&self, tokens: &[String], position: &mut usize) -> Result<f64, AsmError> {
+        let left = self.calc_term(tokens, position)?;
+        let Some(op) = tokens.get(*position).filter(|token| token.as_str() != ")") else {
+            return Ok(left);
+        };
+        *position += 1;
+        let right = self.calc(tokens, position)?;
+        let (a, b) = (left as i64, right as i64);
+        let shift = |shifted: fn(i64, u32) -> Option<i64>| {
+            u32::try_from(b)
+                .ok()
+                .and_then(|b| shifted(a, b))
+                .map(|value| value as f64)
+                .ok_or_else(|| self.error(format!("cannot shift by {} in calculation", b)))
+        };
+        Ok(match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" if b == 0 => return Err(self.error(String::from("remainder of a division by zero in calculation"))),
+            "%" => a.wrapping_rem(b) as f64,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => shift(i64::checked_shl)?,
+            ">>" => shift(i64::checked_shr)?,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => return Err(self.error(format!("unknown operator '{}' in calculation", op))),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[String], position: &mut usize) -> Result<f64, AsmError> {
+        let token = tokens.get(*position).ok_or_else(|| self.error(String::from("incomplete calculation")))?;
+        *position += 1;
+        let unary = |f: fn(f64) -> f64, position: &mut usize| -> Result<f64, AsmError> {
+            Ok(f(self.calc_term(tokens, position)?))
+        };
+        match token.as_str() {
+            "(" => {
+                let value = self.calc(tokens, position)?;
+                if tokens.get(*position).map(String::as_str) != Some(")") {
+                    return Err(self.error(String::from("missing ')' in calculation")));
+                }
+                *position += 1;
+                Ok(value)
+            }
+            "-" => unary(|value| -value, position),
+            "~" => unary(|value| !(value as i64) as f64, position),
+            "!" => unary(|value| (value == 0.0) as i64 as f64, position),
+            "abs" => unary(f64::abs, position),
+            "sqrt" => unary(f64::sqrt, position),
+            "sin" => unary(f64::sin, position),
+            "cos" => unary(f64::cos, position),
+            "tan" => unary(f64::tan, position),
+            "exp" => unary(f64::exp, position),
+            "log" => unary(f64::ln, position),
+            "sign" => unary(f64::signum, position),
+            "ceil" => unary(f64::ceil, position),
+            "floor" => unary(f64::floor, position),
+            "@" => {
+                // The byte already compiled at an address
+                let addr = self.calc_term(tokens, position)? as usize;
+                Ok(addr.checked_sub(PROGRAM_START as usize).and_then(|at| self.rom.get(at)).copied().unwrap_or(0) as f64)
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            _ => {
+                if let Some(value) = self.number(token) {
+                    return Ok(value as f64);
+                }
+                if let Some(&value) = self.constants.get(token) {
+                    return Ok(value);
+                }
+                match self.labels.get(token) {
+                    Some(&addr) => Ok(addr as f64),
+                    None => Err(self.error(format!("undefined name '{}' in calculation", token))),
+                }
+            }
+        }
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.here > 0xFFFF {
+            return Err(self.error(String::from("program does not fit in 64KB of memory")));
+        }
+        let at = self.here - PROGRAM_START as usize;
+        if at >= self.rom.len() {
+            self.rom.resize(at + 1, 0);
+        }
+        self.rom[at] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn emit_data(&mut self, value: i64) -> Result<(), AsmError> {
+        if !(-128..=255).contains(&value) {
+            return Err(self.error(format!("value {} does not fit in a byte", value)));
+        }
+        self.emit_byte(value as u8)
+    }
+
+    fn emit_word(&mut self, word: u16) -> Result<(), AsmError> {
+        if let Some(name) = self.next.take() {
+            self.labels.insert(name, self.here + 1);
+        }
+        let [high, low] = word.to_be_bytes();
+        self.emit_byte(high)?;
+        self.emit_byte(low)
+    }
+
+    // Emits `opcode` with an address in its low 12 bits, or a bare 16-bit
+    // address for `FixupKind::Addr16`, patching it later if not yet known
+    fn emit_address(&mut self, opcode: u16, value: Value, kind: FixupKind) -> Result<(), AsmError> {
+        let limit = match kind {
+            FixupKind::Addr16 => 0xFFFF,
+            _ => 0xFFF,
+        };
+        match value {
+            Value::Known(addr) if (0..=limit).contains(&addr) => self.emit_word(opcode | addr as u16),
+            Value::Known(addr) => Err(self.error(format!("address {:#X} is out of range", addr))),
+            Value::Label(name) => {
+                self.fixups.push(Fixup { addr: self.here, kind, name, line: self.line });
+                self.emit_word(opcode)
+            }
+        }
+    }
+}
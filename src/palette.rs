@@ -0,0 +1,133 @@
+use std::path::Path;
+use crate::error::Chip8Error;
+
+// Colours for the framebuffer, in the terminal as well as in screenshots and
+// videos. Every pixel holds one of four values, one for each combination of
+// the XO-CHIP bit-planes: off, plane 1 only, plane 2 only and both planes.
+// Plain CHIP-8 only ever uses the first two.
+//
+// Colours are either exact RGB or an entry of the xterm 256 colour table.
+// The first 16 of those are the terminal's own theme colours, so a palette
+// made of them follows the terminal's look.
+
+// The named palettes `Palette::parse` accepts
+pub const PALETTES: [&str; 6] = ["default", "green", "amber", "mono", "octo", "gameboy"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Rgb([u8; 3]),
+    Indexed(u8),   // xterm 256 colour table entry
+}
+
+impl Color {
+    // The colour as RGB, taking the 16 theme colours from xterm's defaults
+    pub fn rgb(self) -> [u8; 3] {
+        match self {
+            Color::Rgb(rgb) => rgb,
+            Color::Indexed(index) => xterm_rgb(index),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Color; 4],   // Colour for each plane combination
+}
+
+impl Default for Palette {
+    // The terminal's black, green, red and yellow
+    fn default() -> Palette {
+        Palette { colors: [Color::Indexed(0), Color::Indexed(2), Color::Indexed(1), Color::Indexed(3)] }
+    }
+}
+
+impl Palette {
+    // A palette by name, or a list of two to four colours separated by commas
+    // or whitespace. Each colour is hex RGB such as `#1A2B3C`, with or without
+    // the `#`, or an xterm 256 colour number from 0 to 255. Missing plane 2
+    // and overlap colours repeat the plane 1 colour.
+    pub fn parse(name: &str) -> Option<Palette> {
+        let rgb = |colors: [u32; 4]| colors.map(|color| Color::Rgb([(color >> 16) as u8, (color >> 8) as u8, color as u8]));
+        let colors = match name.trim().to_ascii_lowercase().as_str() {
+            "default" => return Some(Palette::default()),
+            // Shades of a green phosphor monitor
+            "green" => rgb([0x001000, 0x33FF66, 0x1A8033, 0xB3FFC6]),
+            "amber" => rgb([0x1A0F00, 0xFFB000, 0x996600, 0xFFE080]),
+            // White on black
+            "mono" => rgb([0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+            // Octo's default colours
+            "octo" => rgb([0x996600, 0xFFCC00, 0xFF6600, 0x662200]),
+            // The four greens of the original Game Boy screen, dark on light
+            "gameboy" => rgb([0x9BBC0F, 0x0F380F, 0x8BAC0F, 0x306230]),
+            _ => {
+                let parsed: Vec<Color> = name
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|color| !color.is_empty())
+                    .map(color)
+                    .collect::<Option<_>>()?;
+                if !(2..=4).contains(&parsed.len()) {
+                    return None;
+                }
+                std::array::from_fn(|i| *parsed.get(i).unwrap_or(&parsed[1]))
+            }
+        };
+        Some(Palette { colors })
+    }
+
+    // Reads a palette file, which holds what `parse` accepts, here usually a
+    // colour per line. Lines starting with `;` are comments.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Palette, Chip8Error> {
+        let text = std::fs::read_to_string(path)?;
+        let spec: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.starts_with(';')).collect();
+        let spec = spec.join(" ");
+        Palette::parse(&spec).ok_or_else(|| {
+            Chip8Error::InvalidPalette(format!("expected one of {} or two to four colours", PALETTES.join(", ")))
+        })
+    }
+
+    // The RGB colour of a pixel with the given plane bits
+    pub fn color(&self, planes: u8) -> [u8; 3] {
+        self.colors[(planes & 0x3) as usize].rgb()
+    }
+}
+
+// `RRGGBB` with or without a leading `#`, or a 256 colour number
+fn color(text: &str) -> Option<Color> {
+    if let Some(digits) = text.strip_prefix('#').or((text.len() == 6).then_some(text)) {
+        if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let value = u32::from_str_radix(digits, 16).ok()?;
+        return Some(Color::Rgb([(value >> 16) as u8, (value >> 8) as u8, value as u8]));
+    }
+    text.parse().ok().map(Color::Indexed)
+}
+
+// An entry of the xterm 256 colour table: 16 theme colours, a 6x6x6 colour
+// cube and a ramp of 24 greys
+pub fn xterm_rgb(index: u8) -> [u8; 3] {
+    const THEME: [[u8; 3]; 16] = [
+        [0x00, 0x00, 0x00], [0xCD, 0x00, 0x00], [0x00, 0xCD, 0x00], [0xCD, 0xCD, 0x00],
+        [0x00, 0x00, 0xEE], [0xCD, 0x00, 0xCD], [0x00, 0xCD, 0xCD], [0xE5, 0xE5, 0xE5],
+        [0x7F, 0x7F, 0x7F], [0xFF, 0x00, 0x00], [0x00, 0xFF, 0x00], [0xFF, 0xFF, 0x00],
+        [0x5C, 0x5C, 0xFF], [0xFF, 0x00, 0xFF], [0x00, 0xFF, 0xFF], [0xFF, 0xFF, 0xFF],
+    ];
+    const LEVELS: [u8; 6] = [0x00, 0x5F, 0x87, 0xAF, 0xD7, 0xFF];
+    match index {
+        0..=15 => THEME[index as usize],
+        16..=231 => {
+            let cube = index - 16;
+            [LEVELS[(cube / 36) as usize], LEVELS[(cube / 6 % 6) as usize], LEVELS[(cube % 6) as usize]]
+        }
+        _ => [8 + 10 * (index - 232); 3],
+    }
+}
+
+// The entry of the xterm table in `candidates` closest to an RGB colour
+pub fn nearest_xterm(rgb: [u8; 3], candidates: std::ops::RangeInclusive<u8>) -> u8 {
+    let distance = |index: u8| {
+        let other = xterm_rgb(index);
+        (0..3).map(|i| (rgb[i] as i32 - other[i] as i32).pow(2)).sum::<i32>()
+    };
+    candidates.min_by_key(|&index| distance(index)).unwrap_or(0)
+}
@@ -0,0 +1,139 @@
+// Behaviour of the ambiguous CHIP-8 instructions, which changed between the
+// interpreters that ran CHIP-8 programs over the years. ROMs written for one
+// interpreter often misbehave on another, so these are configurable.
+
+// Technical References:
+// https://github.com/Timendus/chip8-test-suite#quirks-test
+// https://chip8.gulrak.net/#quirk5
+
+// How FX55 and FX65 leave the index register after storing/loading registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexIncrement {
+    Unchanged,     // I is left alone (SUPER-CHIP 1.1)
+    ByX,           // I = I + X, an off-by-one in CHIP-48 and SUPER-CHIP 1.0
+    ByXPlusOne,    // I = I + X + 1, as on the COSMAC VIP
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool,                 // 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place
+    pub load_store_index: IndexIncrement,    // FX55/FX65 effect on the index register
+    pub jump_uses_vx: bool,                  // BNNN jumps to XNN + Vx instead of NNN + V0
+    pub vf_reset: bool,                      // 8XY1/8XY2/8XY3 reset VF to 0
+    pub clip_sprites: bool,                  // DXYN clips sprites at the screen edges instead of wrapping them
+    pub display_wait: bool,                  // DXYN waits for the 60Hz vertical blank, drawing at most once a frame
+    pub vf_counts_rows: bool,                // DXYN in hires mode sets VF to the number of rows that collided or were clipped at the bottom
+}
+
+// Names accepted by `Quirks::preset`
+pub const PRESETS: [&str; 6] = ["chip8", "vip", "chip48", "schip1.0", "schip1.1", "xochip"];
+
+impl Default for Quirks {
+    // The behaviour this emulator has always had, which runs most modern
+    // CHIP-8 ROMs correctly.
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::Unchanged,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            vf_counts_rows: false,
+        }
+    }
+}
+
+impl Quirks {
+    // The original interpreter on the RCA COSMAC VIP
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            vf_counts_rows: false,
+        }
+    }
+
+    // CHIP-48 on the HP-48 graphing calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_index: IndexIncrement::ByX,
+            jump_uses_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            vf_counts_rows: false,
+        }
+    }
+
+    // SUPER-CHIP 1.0, which kept the CHIP-48 load/store behaviour
+    pub fn superchip10() -> Quirks {
+        Quirks::chip48()
+    }
+
+    // SUPER-CHIP 1.1, which fixed load/store to leave I untouched and
+    // reports how many rows of a hires sprite hit something
+    pub fn superchip11() -> Quirks {
+        Quirks { load_store_index: IndexIncrement::Unchanged, vf_counts_rows: true, ..Quirks::chip48() }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_index: IndexIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+            vf_counts_rows: false,
+        }
+    }
+
+    // Looks up a preset by one of the names in `PRESETS`
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" => Some(Quirks::default()),
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip1.0" => Some(Quirks::superchip10()),
+            "schip1.1" => Some(Quirks::superchip11()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+// Instruction set understood by the interpreter, on top of the quirks above
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Platform {
+    #[default]
+    Chip8,       // The original CHIP-8 instructions
+    SuperChip,   // Adds the 128x64 mode, scrolling, the big font, RPL flags and exit
+    XoChip,      // SUPER-CHIP plus 64KB of memory, two bit-planes and audio patterns
+}
+
+impl Platform {
+    // The instruction set matching one of the names in `PRESETS`
+    pub fn preset(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "vip" | "chip48" => Some(Platform::Chip8),
+            "schip1.0" | "schip1.1" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // Bytes of RAM available to programs, including the reserved area
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::XoChip => 0x10000,
+            _ => 0x1000,
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+// The random numbers behind CXNN. The generator is part of the machine
+// state, so a run started from the same seed, or restored from a snapshot,
+// draws the same numbers every time.
+
+// Technical References:
+// https://en.wikipedia.org/wiki/Xorshift#xorshift*
+
+// A 64-bit xorshift* generator and its current position, which snapshots
+// store as a plain number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        // Xorshift gets stuck at zero, and nearby seeds should not give
+        // nearby sequences, so the seed is mixed with splitmix64 first
+        Random { state: splitmix64(seed).max(1) }
+    }
+
+    // A generator seeded from the operating system's randomness
+    pub fn from_entropy() -> Random {
+        Random::new(entropy())
+    }
+
+    // A generator at a position taken from `state`, which must not be zero
+    pub fn with_state(state: u64) -> Random {
+        Random { state }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    // The next random byte
+    pub fn byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+// A seed taken from the operating system's randomness, through the random
+// keys the standard library gives each HashMap
+pub fn entropy() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+fn splitmix64(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -1,23 +1,199 @@
 use std::io::{Stdout, self};
-use tui::{widgets::StatefulWidget, Terminal, backend::CrosstermBackend};
-use crossterm::{self, terminal::{enable_raw_mode, EnterAlternateScreen}, execute};
-use tui::{widgets::{Block, Borders}, layout::{Layout, Direction, Constraint}, style::{Color, Style}};
+use tui::{widgets::Widget, Terminal, backend::CrosstermBackend};
+use crossterm::{self, terminal::{enable_raw_mode, disable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, execute};
+use tui::{widgets::{Block, Borders, Paragraph}, layout::{Layout, Direction, Constraint, Rect}, style::{Color, Modifier, Style}};
+use tui::{text::{Span, Spans}, Frame};
 use tui_logger::{TuiLoggerWidget, TuiLoggerLevelOutput};
+use crate::cpu::CHIP8;
+use crate::debugger::Debugger;
+use crate::disasm;
+use crate::display::Display;
+use crate::filter::{DisplayFilter, Filter, Glow, LEVELS};
+use crate::palette::{self, Palette};
 
 pub struct ChipRender{
-    terminal:Terminal<CrosstermBackend<Stdout>>
+    terminal:Terminal<CrosstermBackend<Stdout>>,
+    mode:RenderMode,
+    scale:Option<u16>,   // Size multiplier for CHIP-8 pixels, or None to fill the space available
+    shades:Shades,
+    filter:DisplayFilter,
+    size:Rect,   // Terminal size at the last draw
 }
 
-struct ChipRenderWidget;
+// The terminal colour for every plane combination at every brightness a
+// display filter can fade it to, fully lit last
+type Shades = [[Color; LEVELS as usize]; 4];
 
-impl StatefulWidget for ChipRenderWidget {
-    type State = [[bool; 32]; 64];
-    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer,state: &mut Self::State) {
-        for x in 0..64{
-            for y in 0..32{
-                // Making sure x and y fall withing the terminal height and width
-                if x < area.width && y < area.height  {
-                buf.get_mut(x,y).set_bg(if state[x as usize][y as usize] {tui::style::Color::Green} else {tui::style::Color::Black});
+// How CHIP-8 pixels map onto terminal cells. Each mode packs a block of
+// pixels into every cell, so the same display needs fewer cells the denser
+// the mode. Cells are about twice as tall as they are wide, which makes
+// half-block pixels roughly square.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderMode {
+    Block,       // One pixel per cell, drawn as the cell's background
+    HalfBlock,   // Two pixels stacked in each cell with '▀', top in the foreground
+    Braille,     // A 2x4 block of pixels per cell as braille dots, in one colour
+}
+
+impl RenderMode {
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name.to_ascii_lowercase().as_str() {
+            "block" => Some(RenderMode::Block),
+            "half" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None,
+        }
+    }
+
+    // Pixels across and down in one cell
+    fn cell(self) -> (usize, usize) {
+        match self {
+            RenderMode::Block => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+}
+
+// How many colours the terminal can show. Palette colours it lacks are
+// replaced with the closest it has.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    TrueColor,   // Any RGB colour
+    Ansi256,     // The xterm 256 colour table
+    Ansi16,      // Only the 16 theme colours
+}
+
+// The 16 theme colours in xterm order
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow, Color::Blue, Color::Magenta, Color::Cyan, Color::Gray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::LightYellow, Color::LightBlue, Color::LightMagenta,
+    Color::LightCyan, Color::White,
+];
+
+impl ColorDepth {
+    pub fn from_name(name: &str) -> Option<ColorDepth> {
+        match name.to_ascii_lowercase().as_str() {
+            "truecolor" | "24bit" => Some(ColorDepth::TrueColor),
+            "256" => Some(ColorDepth::Ansi256),
+            "16" => Some(ColorDepth::Ansi16),
+            _ => None,
+        }
+    }
+
+    // Terminals with RGB colour set COLORTERM to say so, and TERM names
+    // the ones with 256 colours. Anything else gets the 16 theme colours.
+    pub fn detect() -> ColorDepth {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    fn color(self, color: palette::Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, palette::Color::Rgb([r, g, b])) => Color::Rgb(r, g, b),
+            (ColorDepth::Ansi256, palette::Color::Rgb(rgb)) => Color::Indexed(palette::nearest_xterm(rgb, 16..=255)),
+            (ColorDepth::Ansi16, palette::Color::Rgb(rgb)) => ANSI_COLORS[palette::nearest_xterm(rgb, 0..=15) as usize],
+            (_, palette::Color::Indexed(index)) if index < 16 => ANSI_COLORS[index as usize],
+            (ColorDepth::Ansi16, palette::Color::Indexed(index)) => {
+                ANSI_COLORS[palette::nearest_xterm(palette::xterm_rgb(index), 0..=15) as usize]
+            }
+            (_, palette::Color::Indexed(index)) => Color::Indexed(index),
+        }
+    }
+}
+
+// The braille dot for each pixel of a 2x4 cell, indexed by [y][x]
+const BRAILLE_DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+// Draws the display centred in its area inside a border
+struct ChipRenderWidget<'a>{
+    filter:&'a DisplayFilter,
+    mode:RenderMode,
+    scale:Option<u16>,
+    shades:&'a Shades,
+}
+
+impl ChipRenderWidget<'_> {
+    // Cells across and down the display takes at a scale
+    fn cells(&self, scale: usize) -> (usize, usize) {
+        let (across, down) = self.mode.cell();
+        ((self.filter.width() * scale).div_ceil(across), (self.filter.height() * scale).div_ceil(down))
+    }
+
+    // The fixed scale, or the largest one that fits inside `width` by
+    // `height` cells. Displays too big for the space get clipped at scale 1.
+    fn scale(&self, width: usize, height: usize) -> usize {
+        match self.scale {
+            Some(scale) => scale as usize,
+            // An empty display fits at any scale, so searching would never end
+            None if self.filter.width() == 0 || self.filter.height() == 0 => 1,
+            None => (2..)
+                .take_while(|&scale| {
+                    let (columns, rows) = self.cells(scale);
+                    columns <= width && rows <= height
+                })
+                .last()
+                .unwrap_or(1),
+        }
+    }
+}
+
+impl Widget for ChipRenderWidget<'_> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        let scale = self.scale(area.width.saturating_sub(2) as usize, area.height.saturating_sub(2) as usize);
+        let (columns, rows) = self.cells(scale);
+        // Centring the bordered display, or filling the area if it is too big
+        let width = (columns as u16 + 2).min(area.width);
+        let height = (rows as u16 + 2).min(area.height);
+        let outer = Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height);
+        let title = format!(" {}x{} ", self.filter.width(), self.filter.height());
+        let block = panel(title);
+        let inner = block.inner(outer);
+        block.render(outer, buf);
+
+        let (across, down) = self.mode.cell();
+        let state = self.filter;
+        // A pixel at `scale`, off outside the display
+        let pixel = |x: usize, y: usize| {
+            let (x, y) = (x / scale, y / scale);
+            if x < state.width() && y < state.height() { state.glow(x, y) } else { Glow::default() }
+        };
+        let color = |glow: Glow| self.shades[glow.planes as usize][glow.level as usize];
+        for row in 0..inner.height {
+            for column in 0..inner.width {
+                let (x, y) = (column as usize * across, row as usize * down);
+                let cell = buf.get_mut(inner.x + column, inner.y + row);
+                match self.mode {
+                    RenderMode::Block => {
+                        cell.set_char(' ').set_bg(color(pixel(x, y)));
+                    }
+                    RenderMode::HalfBlock => {
+                        cell.set_char('▀').set_fg(color(pixel(x, y))).set_bg(color(pixel(x, y + 1)));
+                    }
+                    RenderMode::Braille => {
+                        // A cell only has one foreground colour, so the
+                        // brightest pixel in it wins, then the highest
+                        // plane combination
+                        let (mut dots, mut brightest) = (0, Glow::default());
+                        for (dy, line) in BRAILLE_DOTS.iter().enumerate() {
+                            for (dx, dot) in line.iter().enumerate() {
+                                let glow = pixel(x + dx, y + dy);
+                                if glow.planes != 0 {
+                                    dots |= dot;
+                                    brightest = brightest.max(glow);
+                                }
+                            }
+                        }
+                        let symbol = char::from_u32(0x2800 + dots as u32).unwrap_or(' ');
+                        cell.set_char(symbol).set_fg(color(brightest)).set_bg(self.shades[0][0]);
+                    }
                 }
             }
         }
@@ -25,15 +201,45 @@ impl StatefulWidget for ChipRenderWidget {
 }
 
 impl ChipRender{
-    pub fn setup()->Result<ChipRender,io::Error>{
+    pub fn setup(mode:RenderMode,scale:Option<u16>,palette:&Palette,depth:ColorDepth,filter:Filter)->Result<ChipRender,io::Error>{
         enable_raw_mode()?;
         let mut stdout = io::stdout();
         execute!(stdout,EnterAlternateScreen)?;
         let terminal = Terminal::new(CrosstermBackend::new(stdout))?;
-        Ok(ChipRender{terminal})
+        // Fading needs shades between the palette colours
+        let filter = match filter {
+            Filter::Phosphor { .. } if depth == ColorDepth::Ansi16 => {
+                log::info!("Phosphor fading needs more than 16 colours, merging frames instead.");
+                Filter::Merge
+            }
+            filter => filter,
+        };
+        Ok(ChipRender{terminal,mode,scale,shades:shades(palette,depth),filter:DisplayFilter::new(filter),size:Rect::default()})
+    }
+
+    // Puts the terminal back the way we found it
+    pub fn restore(&mut self) -> Result<(), io::Error> {
+        disable_raw_mode()?;
+        execute!(self.terminal.backend_mut(), LeaveAlternateScreen)?;
+        self.terminal.show_cursor()
+    }
+
+    // Feeds the display at the end of a frame through the display filter,
+    // returning whether the picture on screen needs to change
+    pub fn update(&mut self, display: &Display) -> bool {
+        self.filter.update(display)
     }
 
-    pub fn render(& mut self,display:& mut[[bool; 32]; 64]) -> () {
+    // Whether the terminal was resized since the last draw, so the layout
+    // has to be redrawn even if the display did not change
+    pub fn resized(&self) -> bool {
+        self.terminal.size().is_ok_and(|size| size != self.size)
+    }
+
+    // Draws the display on the left and the instruction log on the right,
+    // with the debugger panels above the log when debugging
+    pub fn render(&mut self, chip8: &CHIP8, debugger: Option<&Debugger>) {
+        let (mode, scale, shades, filter) = (self.mode, self.scale, &self.shades, &self.filter);
         self.terminal.draw(|frame|{
             /* divide screen for the logger and display */
             let rects = Layout::default()
@@ -42,7 +248,12 @@ impl ChipRender{
             .split(frame.size());
          
         /* draw the display */
-        frame.render_stateful_widget(ChipRenderWidget, rects[0],display);
+        frame.render_widget(ChipRenderWidget{filter,mode,scale,shades}, rects[0]);
+
+        let log_area = match debugger {
+            Some(debugger) => render_debugger(frame, rects[1], chip8, debugger),
+            None => rects[1],
+        };
         
         let tui_w = TuiLoggerWidget::default()
             .block(
@@ -64,9 +275,98 @@ impl ChipRender{
             .style_info(Style::default().fg(Color::Green));
         
         /* draw the logger */
-        frame.render_widget(tui_w, rects[1]);
+        frame.render_widget(tui_w, log_area);
             
         }).expect("Failed to render display");
+        self.size = self.terminal.size().unwrap_or_default();
     }
 
 }
+
+fn shades(palette: &Palette, depth: ColorDepth) -> Shades {
+    let full = LEVELS as usize - 1;
+    let off = palette.color(0);
+    std::array::from_fn(|planes| {
+        std::array::from_fn(|level| {
+            if level == full || planes == 0 {
+                // Kept as given, so theme colours follow the terminal's theme
+                return depth.color(palette.colors[planes]);
+            }
+            let lit = palette.color(planes as u8);
+            let mix = |i: usize| ((off[i] as usize * (full - level) + lit[i] as usize * level) / full) as u8;
+            depth.color(palette::Color::Rgb([mix(0), mix(1), mix(2)]))
+        })
+    })
+}
+
+fn panel(title: String) -> Block<'static> {
+    Block::default()
+        .title(title)
+        .border_style(Style::default().fg(Color::White).bg(Color::Black))
+        .borders(Borders::ALL)
+}
+
+// Draws the registers, code and stack panels at the top of `area`, returning
+// the space left below them for the log
+fn render_debugger(frame: &mut Frame<CrosstermBackend<Stdout>>, area: Rect, chip8: &CHIP8, debugger: &Debugger) -> Rect {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(9), Constraint::Percentage(60), Constraint::Min(3)].as_ref())
+        .split(area);
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(70), Constraint::Percentage(30)].as_ref())
+        .split(rows[1]);
+
+    // V0-VF four to a line, then I, PC, the timers and the next instruction
+    let mut registers: Vec<Spans> = chip8
+        .variables
+        .chunks(4)
+        .enumerate()
+        .map(|(row, values)| {
+            let line: Vec<String> =
+                values.iter().enumerate().map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value)).collect();
+            Spans::from(line.join("  "))
+        })
+        .collect();
+    registers.push(Spans::from(format!("I  {:04X}  PC {:04X}", chip8.index, chip8.pc)));
+    registers.push(Spans::from(format!("DT {:02X}    ST {:02X}", chip8.delay_timer, chip8.sound_timer)));
+    let (instruction, _) = disasm::decode(&chip8.memory, chip8.pc, chip8.config.platform);
+    registers.push(Spans::from(format!("{:04X}: {}", chip8.pc, instruction)));
+    let state = if debugger.paused { "paused" } else { "running" };
+    frame.render_widget(Paragraph::new(registers).block(panel(format!(" Registers ({}) ", state))), rows[0]);
+
+    // Instructions around the cursor, marking breakpoints and the program counter
+    let lines = columns[0].height.saturating_sub(2);
+    let first = debugger.cursor.wrapping_sub(lines / 2 * 2);
+    let code: Vec<Spans> = (0..lines)
+        .map(|line| {
+            let addr = first.wrapping_add(line * 2);
+            let breakpoint = if debugger.breakpoints.contains(&addr) { '*' } else { ' ' };
+            let pc = if addr == chip8.pc { '>' } else { ' ' };
+            let (instruction, _) = disasm::decode(&chip8.memory, addr, chip8.config.platform);
+            let text = format!("{}{} {:04X}  {}", breakpoint, pc, addr, instruction);
+            let style = if addr == debugger.cursor {
+                Style::default().add_modifier(Modifier::REVERSED)
+            } else {
+                Style::default()
+            };
+            Spans::from(Span::styled(text, style))
+        })
+        .collect();
+    let help = " Code  F4 back  F5 run/pause  F6 step  F7 over  F8 to cursor  F9 break ";
+    frame.render_widget(Paragraph::new(code).block(panel(help.to_string())), columns[0]);
+
+    // Return addresses, innermost call first
+    let stack: Vec<Spans> = chip8.stack.iter().rev().map(|addr| Spans::from(format!("{:04X}", addr))).collect();
+    frame.render_widget(Paragraph::new(stack).block(panel(String::from(" Stack "))), columns[1]);
+
+    rows[2]
+}
+
+impl Drop for ChipRender {
+    fn drop(&mut self) {
+        // Nothing more can be done if restoring fails while tearing down
+        let _ = self.restore();
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+use crate::cpu::{CHIP8, Run};
+use crate::snapshot::Snapshot;
+
+// Stepping backwards in time. A Rewind keeps a ring buffer of snapshots
+// taken as the emulator runs, normally one at the start of every frame, and
+// drops the oldest once it is full.
+//
+// Going back a frame restores the latest snapshot. Going back a single
+// instruction restores it too and then runs forward again to the instruction
+// before the current one. That replay is exact because the machine is
+// deterministic: each checkpoint remembers the keys that were held and how
+// many instructions ran after it, and the timers never tick in between.
+
+struct Checkpoint {
+    snapshot: Snapshot,
+    keypad: [bool; 16],   // Keys held while the instructions after the snapshot ran
+    steps: u32,           // Instructions executed since the snapshot was taken
+}
+
+pub struct Rewind {
+    checkpoints: VecDeque<Checkpoint>,
+    capacity: usize,      // Most checkpoints kept, zero to disable rewinding
+}
+
+impl Rewind {
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind { checkpoints: VecDeque::new(), capacity }
+    }
+
+    // Number of checkpoints that can still be gone back to
+    pub fn len(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checkpoints.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+    }
+
+    // Remembers the current state as the point the next instructions run
+    // from. The timers must not tick until the next checkpoint is taken.
+    pub fn checkpoint(&mut self, chip8: &CHIP8) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.checkpoints.len() == self.capacity {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints.push_back(Checkpoint { snapshot: chip8.snapshot(), keypad: chip8.keypad, steps: 0 });
+    }
+
+    // Counts the instructions executed since the latest checkpoint
+    pub fn record(&mut self, run: &Run) {
+        if let Some(checkpoint) = self.checkpoints.back_mut() {
+            checkpoint.steps += run.cycles;
+        }
+    }
+
+    // Restores the latest checkpoint, usually the start of the frame that
+    // just ran, and forgets it so the next call goes back further. Returns
+    // false once there is nothing left to go back to.
+    pub fn back_frame(&mut self, chip8: &mut CHIP8) -> bool {
+        match self.checkpoints.pop_back() {
+            Some(checkpoint) => {
+                chip8.restore(&checkpoint.snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Undoes the last instruction executed. Returns false if it was run
+    // before the oldest checkpoint.
+    pub fn back_step(&mut self, chip8: &mut CHIP8) -> bool {
+        // Checkpoints with nothing run after them hold the current state
+        while self.checkpoints.back().is_some_and(|checkpoint| checkpoint.steps == 0) {
+            self.checkpoints.pop_back();
+        }
+        let Some(checkpoint) = self.checkpoints.back_mut() else {
+            return false;
+        };
+        checkpoint.steps -= 1;
+        let keypad = chip8.keypad;
+        chip8.restore(&checkpoint.snapshot);
+        chip8.keypad = checkpoint.keypad;
+        for _ in 0..checkpoint.steps {
+            // These instructions all succeeded the first time they ran
+            if let Err(error) = chip8.step() {
+                log::error!("Replay failed: {}", error);
+                break;
+            }
+        }
+        chip8.keypad = keypad;
+        true
+    }
+}
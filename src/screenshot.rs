@@ -0,0 +1,144 @@
+use std::io;
+use std::path::Path;
+use crate::display::Display;
+use crate::error::Chip8Error;
+use crate::palette::Palette;
+
+// Screenshots of the framebuffer. Every CHIP-8 pixel becomes a `scale` by
+// `scale` block of image pixels, coloured from a palette. The encoders are
+// small enough to write by hand, so no image library is needed:
+//
+// - PBM (P4) is black and white, with lit pixels on any plane in white
+// - PPM (P6) is 8-bit RGB
+// - PNG is 8-bit RGB, stored without compression since CHIP-8 screens are tiny
+
+// Technical References:
+// https://netpbm.sourceforge.net/doc/pbm.html
+// https://netpbm.sourceforge.net/doc/ppm.html
+// https://www.w3.org/TR/png/
+// https://www.rfc-editor.org/rfc/rfc1950 (zlib) and rfc1951 (deflate)
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Pbm,
+    Ppm,
+    Png,
+}
+
+impl ImageFormat {
+    // The format for a file name's extension
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "pbm" => Some(ImageFormat::Pbm),
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// Writes the display to `path` in the format its extension names
+pub fn save<P: AsRef<Path>>(display: &Display, path: P, scale: usize, palette: &Palette) -> Result<(), Chip8Error> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "screenshots must end in .png, .ppm or .pbm"))?;
+    std::fs::write(path, encode(display, format, scale, palette))?;
+    Ok(())
+}
+
+pub fn encode(display: &Display, format: ImageFormat, scale: usize, palette: &Palette) -> Vec<u8> {
+    let scale = scale.max(1);
+    let (width, height) = (display.width() * scale, display.height() * scale);
+    match format {
+        ImageFormat::Pbm => {
+            // Rows are packed 8 pixels to a byte, most significant bit first,
+            // with 1 meaning black
+            let mut out = format!("P4\n{} {}\n", width, height).into_bytes();
+            for y in 0..height {
+                let mut row = vec![0xFF; width.div_ceil(8)];
+                for x in 0..width {
+                    if display.get(x / scale, y / scale) {
+                        row[x / 8] &= !(0x80 >> (x % 8));
+                    }
+                }
+                out.extend_from_slice(&row);
+            }
+            out
+        }
+        ImageFormat::Ppm => {
+            let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+            for y in 0..height {
+                out.extend_from_slice(&rgb_row(display, y, scale, palette));
+            }
+            out
+        }
+        ImageFormat::Png => {
+            // Every row starts with its filter type, 0 for none
+            let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+            for y in 0..height {
+                raw.push(0);
+                raw.extend_from_slice(&rgb_row(display, y, scale, palette));
+            }
+            let mut header = Vec::with_capacity(13);
+            header.extend_from_slice(&(width as u32).to_be_bytes());
+            header.extend_from_slice(&(height as u32).to_be_bytes());
+            // 8 bits per channel, RGB, deflate, no filtering, no interlacing
+            header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+            let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+            png_chunk(&mut out, b"IHDR", &header);
+            png_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+            png_chunk(&mut out, b"IEND", &[]);
+            out
+        }
+    }
+}
+
+// One row of image pixels as RGB triples
+fn rgb_row(display: &Display, y: usize, scale: usize, palette: &Palette) -> Vec<u8> {
+    (0..display.width() * scale)
+        .flat_map(|x| palette.color(display.pixel(x / scale, y / scale)))
+        .collect()
+}
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// A zlib stream holding `data` in uncompressed deflate blocks, which can
+// carry at most 65535 bytes each
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        out.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 })
+    })
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    (b << 16) | a
+}
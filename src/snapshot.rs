@@ -0,0 +1,260 @@
+use std::path::Path;
+use crate::audio::Waveform;
+use crate::cpu::{Config, STACK_SIZE};
+use crate::display::{Display, HIRES_HEIGHT, HIRES_WIDTH, LORES_HEIGHT, LORES_WIDTH};
+use crate::error::Chip8Error;
+use crate::quirks::{IndexIncrement, Platform, Quirks};
+use crate::random::Random;
+
+// Save states. `CHIP8::snapshot` captures the whole machine in a Snapshot,
+// which can be kept in memory and handed back to `CHIP8::restore`, or turned
+// into bytes and written to a file.
+//
+// The file format is a 4 byte magic number and a 16-bit format version
+// followed by every field in a fixed order, with multi-byte numbers stored
+// little-endian. Readers reject versions they do not know, so the layout can
+// change later by bumping `VERSION`.
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u16 = 5;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub(crate) config: Config,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) pc: u16,
+    pub(crate) index: u16,
+    pub(crate) stack: Vec<u16>,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) variables: [u8; 16],
+    pub(crate) display: Display,
+    pub(crate) rpl: [u8; 16],
+    pub(crate) vblank: bool,
+    pub(crate) plane: u8,
+    pub(crate) audio_pattern: Option<[u8; 16]>,
+    pub(crate) pitch: u8,
+    pub(crate) rng: Random,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        write_config(&mut out, &self.config);
+
+        out.extend_from_slice(&self.pc.to_le_bytes());
+        out.extend_from_slice(&self.index.to_le_bytes());
+        out.push(self.stack.len() as u8);
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_le_bytes());
+        }
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend_from_slice(&self.variables);
+        out.extend_from_slice(&self.rpl);
+        out.push(self.vblank as u8);
+        out.push(self.plane);
+        out.push(self.audio_pattern.is_some() as u8);
+        out.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        out.push(self.pitch);
+        out.extend_from_slice(&self.rng.state().to_le_bytes());
+
+        out.push(self.display.is_hires() as u8);
+        for row in self.display.rows() {
+            out.extend_from_slice(row);
+        }
+        out.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.memory);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, Chip8Error> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err(invalid("not a save state"));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(Chip8Error::InvalidSnapshot(format!("unsupported save state version {}", version)));
+        }
+
+        let config = read_config(&mut reader)?;
+        let platform = config.platform;
+
+        let pc = reader.u16()?;
+        let index = reader.u16()?;
+        let depth = reader.u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(invalid("stack too deep"));
+        }
+        let stack = (0..depth).map(|_| reader.u16()).collect::<Result<Vec<u16>, Chip8Error>>()?;
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let variables = reader.array()?;
+        let rpl = reader.array()?;
+        let vblank = reader.bool()?;
+        let plane = reader.u8()?;
+        let has_pattern = reader.bool()?;
+        let pattern = reader.array()?;
+        let pitch = reader.u8()?;
+        let state = reader.u64()?;
+        if state == 0 {
+            return Err(invalid("random number generator state is zero"));
+        }
+        let rng = Random::with_state(state);
+
+        let (width, height) = if reader.bool()? { (HIRES_WIDTH, HIRES_HEIGHT) } else { (LORES_WIDTH, LORES_HEIGHT) };
+        let mut display = Display::new(width, height);
+        let pixels = reader.take(width * height)?;
+        for (i, &pixel) in pixels.iter().enumerate() {
+            display.set_pixel(i % width, i / width, pixel & 0x3);
+        }
+        let memory_size = reader.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(invalid("memory size does not match the platform"));
+        }
+        let memory = reader.take(memory_size)?.to_vec();
+        if !reader.bytes.is_empty() {
+            return Err(invalid("unexpected data at the end"));
+        }
+
+        Ok(Snapshot {
+            config,
+            memory,
+            pc,
+            index,
+            stack,
+            delay_timer,
+            sound_timer,
+            variables,
+            display,
+            rpl,
+            vblank,
+            plane,
+            audio_pattern: has_pattern.then_some(pattern),
+            pitch,
+            rng,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Chip8Error> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, Chip8Error> {
+        Snapshot::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+// Settings are stored the same way in save states and movies
+pub(crate) fn write_config(out: &mut Vec<u8>, config: &Config) {
+    out.extend_from_slice(&config.ips.to_le_bytes());
+    out.extend_from_slice(&config.beep_frequency.to_le_bytes());
+    out.push(config.platform as u8);
+    let quirks = &config.quirks;
+    out.push(quirks.shift_uses_vy as u8);
+    out.push(quirks.load_store_index as u8);
+    out.push(quirks.jump_uses_vx as u8);
+    out.push(quirks.vf_reset as u8);
+    out.push(quirks.clip_sprites as u8);
+    out.push(quirks.display_wait as u8);
+    out.push(quirks.vf_counts_rows as u8);
+    out.push(config.seed.is_some() as u8);
+    out.extend_from_slice(&config.seed.unwrap_or(0).to_le_bytes());
+    out.push(config.waveform as u8);
+    out.push(config.volume);
+    out.push(config.mute as u8);
+}
+
+pub(crate) fn read_config(reader: &mut Reader) -> Result<Config, Chip8Error> {
+    let ips = i32::from_le_bytes(reader.array()?);
+    if ips <= 0 {
+        return Err(invalid("instructions per second must be at least 1"));
+    }
+    let beep_frequency = f32::from_le_bytes(reader.array()?);
+    if !beep_frequency.is_finite() || beep_frequency <= 0.0 {
+        return Err(invalid("beep frequency must be a positive number"));
+    }
+    let platform = match reader.u8()? {
+        0 => Platform::Chip8,
+        1 => Platform::SuperChip,
+        2 => Platform::XoChip,
+        _ => return Err(invalid("unknown platform")),
+    };
+    let shift_uses_vy = reader.bool()?;
+    let load_store_index = match reader.u8()? {
+        0 => IndexIncrement::Unchanged,
+        1 => IndexIncrement::ByX,
+        2 => IndexIncrement::ByXPlusOne,
+        _ => return Err(invalid("unknown load/store quirk")),
+    };
+    let quirks = Quirks {
+        shift_uses_vy,
+        load_store_index,
+        jump_uses_vx: reader.bool()?,
+        vf_reset: reader.bool()?,
+        clip_sprites: reader.bool()?,
+        display_wait: reader.bool()?,
+        vf_counts_rows: reader.bool()?,
+    };
+    let has_seed = reader.bool()?;
+    let seed = reader.u64()?;
+    let waveform = match reader.u8()? {
+        0 => Waveform::Square,
+        1 => Waveform::Sine,
+        2 => Waveform::Triangle,
+        _ => return Err(invalid("unknown waveform")),
+    };
+    let (volume, mute) = (reader.u8()?, reader.bool()?);
+    Ok(Config { ips, beep_frequency, waveform, volume, mute, platform, quirks, seed: has_seed.then_some(seed) })
+}
+
+fn invalid(reason: &str) -> Chip8Error {
+    Chip8Error::InvalidSnapshot(reason.to_string())
+}
+
+// Takes values off the front of a byte slice, failing if it runs out
+pub(crate) struct Reader<'a> {
+    pub(crate) bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.bytes.len() < len {
+            return Err(invalid("the data ends early"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> Result<[u8; N], Chip8Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, Chip8Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, Chip8Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, Chip8Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, Chip8Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, Chip8Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use crate::display::{Display, LORES_HEIGHT, LORES_WIDTH};
+use crate::error::Chip8Error;
+use crate::palette::Palette;
+
+// Video recordings of the framebuffer, one picture per 60Hz frame, written
+// as the frames arrive:
+//
+// - GIF: animated and looping, with the palette as its colour table. GIF
+//   delays count hundredths of a second and many viewers slow down frames
+//   shorter than two of them, so frames that change faster are dropped,
+//   leaving at most 50 pictures a second.
+// - Y4M: uncompressed 4:4:4 YUV at 60 frames a second, which ffmpeg and most
+//   video tools read directly.
+// - Raw: bare 24-bit RGB frames back to back, with nothing around them.
+//
+// Every frame has the size of a 64x32 display at `scale` image pixels per
+// CHIP-8 pixel. 128x64 frames are drawn with pixels half as big, so hires
+// programs want an even scale.
+
+// Technical References:
+// https://www.w3.org/Graphics/GIF/spec-gif89a.txt
+// https://wiki.multimedia.cx/index.php/YUV4MPEG2
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VideoFormat {
+    Gif,
+    Y4m,
+    Raw,
+}
+
+impl VideoFormat {
+    // The format for a file name's extension
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            "rgb" => Some(VideoFormat::Raw),
+            _ => None,
+        }
+    }
+}
+
+pub struct Video {
+    out: BufWriter<File>,
+    format: VideoFormat,
+    palette: Palette,
+    width: usize,
+    height: usize,
+    frames: u64,               // Frames captured so far
+    pending: Option<Picture>,  // GIF: the picture waiting to learn how long it lasts
+    finished: bool,
+}
+
+// A GIF picture: palette indices, and the hundredth of a second it appears at
+struct Picture {
+    indices: Vec<u8>,
+    start: u64,
+}
+
+impl Video {
+    // Creates the file at `path`, in the format its extension names
+    pub fn create<P: AsRef<Path>>(path: P, scale: usize, palette: &Palette) -> Result<Video, Chip8Error> {
+        let path = path.as_ref();
+        let format = VideoFormat::from_path(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "videos must end in .gif, .y4m or .rgb"))?;
+        let scale = scale.max(1);
+        // GIFs store their size in 16 bits
+        let (width, height) = match (LORES_WIDTH.checked_mul(scale), LORES_HEIGHT.checked_mul(scale)) {
+            (Some(width), Some(height)) if format != VideoFormat::Gif || width <= u16::MAX as usize => (width, height),
+            _ => {
+                let message = format!("an image scale of {} is too large for {}", scale, path.display());
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message).into());
+            }
+        };
+        let mut video = Video {
+            out: BufWriter::new(File::create(path)?),
+            format,
+            palette: *palette,
+            width,
+            height,
+            frames: 0,
+            pending: None,
+            finished: false,
+        };
+        video.header()?;
+        Ok(video)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Adds the display as the next frame
+    pub fn frame(&mut self, display: &Display) -> Result<(), Chip8Error> {
+        let indices = self.sample(display);
+        match self.format {
+            VideoFormat::Gif => {
+                let time = self.frames * 100 / 60;
+                match &mut self.pending {
+                    Some(picture) if picture.indices == indices => {}
+                    Some(picture) if time - picture.start < 2 => picture.indices = indices,
+                    _ => {
+                        if let Some(picture) = self.pending.take() {
+                            self.gif_picture(&picture, time - picture.start)?;
+                        }
+                        self.pending = Some(Picture { indices, start: time });
+                    }
+                }
+            }
+            VideoFormat::Y4m => {
+                let yuv: [[u8; 3]; 4] = std::array::from_fn(|planes| yuv(self.palette.color(planes as u8)));
+                self.out.write_all(b"FRAME\n")?;
+                // Y, U and V each come as a whole plane
+                let planes: [Vec<u8>; 3] =
+                    std::array::from_fn(|plane| indices.iter().map(|&index| yuv[index as usize][plane]).collect());
+                for plane in planes {
+                    self.out.write_all(&plane)?;
+                }
+            }
+            VideoFormat::Raw => {
+                let bytes: Vec<u8> = indices.iter().flat_map(|&index| self.palette.color(index)).collect();
+                self.out.write_all(&bytes)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    // Writes out anything still buffered. Dropping a video does the same but
+    // has nowhere to report errors.
+    pub fn finish(&mut self) -> Result<(), Chip8Error> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        if self.format == VideoFormat::Gif {
+            if let Some(picture) = self.pending.take() {
+                let end = self.frames * 100 / 60;
+                self.gif_picture(&picture, (end - picture.start).max(2))?;
+            }
+            self.out.write_all(&[0x3B])?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    fn header(&mut self) -> Result<(), Chip8Error> {
+        let (width, height) = (self.width, self.height);
+        match self.format {
+            VideoFormat::Gif => {
+                self.out.write_all(b"GIF89a")?;
+                self.out.write_all(&(width as u16).to_le_bytes())?;
+                self.out.write_all(&(height as u16).to_le_bytes())?;
+                // A global colour table of 4 entries, background colour 0
+                self.out.write_all(&[0x91, 0, 0])?;
+                for planes in 0..4 {
+                    self.out.write_all(&self.palette.color(planes))?;
+                }
+                // Loop forever
+                self.out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+            }
+            VideoFormat::Y4m => {
+                writeln!(self.out, "YUV4MPEG2 W{} H{} F60:1 Ip A1:1 C444", width, height)?;
+            }
+            VideoFormat::Raw => {}
+        }
+        Ok(())
+    }
+
+    // The palette index of every image pixel
+    fn sample(&self, display: &Display) -> Vec<u8> {
+        let mut indices = Vec::with_capacity(self.width * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                indices.push(display.pixel(x * display.width() / self.width, y * display.height() / self.height));
+            }
+        }
+        indices
+    }
+
+    fn gif_picture(&mut self, picture: &Picture, delay: u64) -> Result<(), Chip8Error> {
+        // Graphic control extension holding the delay
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&(delay.min(u16::MAX as u64) as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+        // Image descriptor covering the whole screen, without a local colour table
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(self.width as u16).to_le_bytes())?;
+        self.out.write_all(&(self.height as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, GIF_CODE_SIZE])?;
+        for block in lzw(&picture.indices).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])?;
+        Ok(())
+    }
+}
+
+impl Drop for Video {
+    fn drop(&mut self) {
+        // Nothing more can be done if this fails while tearing down
+        let _ = self.finish();
+    }
+}
+
+// Bits in a palette index, the smallest GIF allows
+const GIF_CODE_SIZE: u8 = 2;
+
+// GIF's variable-length LZW
+fn lzw(indices: &[u8]) -> Vec<u8> {
+    let clear = 1u16 << GIF_CODE_SIZE;
+    let end = clear + 1;
+    let mut codes = Codes { out: Vec::new(), bits: 0, count: 0 };
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut size = GIF_CODE_SIZE as u32 + 1;
+    let mut next = end + 1;
+    codes.push(clear, size);
+    if let Some((&first, rest)) = indices.split_first() {
+        let mut prefix = first as u16;
+        for &index in rest {
+            if let Some(&code) = table.get(&(prefix, index)) {
+                prefix = code;
+                continue;
+            }
+            codes.push(prefix, size);
+            if next < 4096 {
+                table.insert((prefix, index), next);
+                next += 1;
+                // The decoder adds each code one step later, so widen once the
+                // code it is about to add no longer fits
+                if next > 1 << size && size < 12 {
+                    size += 1;
+                }
+            } else {
+                // The table is full, start again from the single indices
+                codes.push(clear, size);
+                table.clear();
+                size = GIF_CODE_SIZE as u32 + 1;
+                next = end + 1;
+            }
+            prefix = index as u16;
+        }
+        codes.push(prefix, size);
+    }
+    codes.push(end, size);
+    if codes.count > 0 {
+        codes.out.push(codes.bits as u8);
+    }
+    codes.out
+}
+
+// Packs codes into bytes least significant bit first
+struct Codes {
+    out: Vec<u8>,
+    bits: u32,     // Bits not yet written out
+    count: u32,    // How many of them there are
+}
+
+impl Codes {
+    fn push(&mut self, code: u16, size: u32) {
+        self.bits |= (code as u32) << self.count;
+        self.count += size;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+}
+
+// An RGB colour in limited range BT.601 YUV, as video players expect
+fn yuv([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    [
+        (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8,
+        (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+        (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+    ]
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use std::path::Path;
+use chip_8::asm::assemble;
+use chip_8::cpu::{CHIP8, Config, PROGRAM_START};
+use chip_8::disasm::disassemble;
+
+// The assembler has to turn anything the disassembler prints back into the
+// same bytes. Every bundled ROM is taken through both for each instruction
+// set, along with a few programs written by hand.
+
+fn bytes(source: &str) -> Vec<u8> {
+    assemble(source, Path::new("test.asm")).unwrap()
+}
+
+#[test]
+fn bundled_roms_round_trip() {
+    let mut roms: Vec<_> = std::fs::read_dir("ROMS").unwrap().map(|entry| entry.unwrap().path()).collect();
+    roms.sort();
+    assert!(!roms.is_empty());
+    for platform in ["chip8", "schip1.1", "xochip"] {
+        for rom in &roms {
+            let program = std::fs::read(rom).unwrap();
+            let mut chip = CHIP8::with_config(Config::preset(platform).unwrap());
+            chip.load_rom(&program).unwrap();
+            let start = PROGRAM_START as usize;
+            let source = disassemble(&chip.memory, start..start + program.len(), chip.config.platform).to_string();
+            let assembled = assemble(&source, rom).unwrap_or_else(|error| panic!("{} on {}: {}", platform, rom.display(), error));
+            assert!(assembled == program, "{} on {} does not round trip", platform, rom.display());
+        }
+    }
+}
+
+#[test]
+fn labels_and_constants() {
+    let source = "
+        SPEED EQU 2
+        start: ADD V0, SPEED      ; labels may share a line
+            SE V0, SPEED + 8
+            JP start
+        sprite:
+            LD I, sprite + 2
+    ";
+    assert_eq!(bytes(source), [0x70, 0x02, 0x30, 0x0A, 0x12, 0x00, 0xA2, 0x08]);
+}
+
+#[test]
+fn data_directives() {
+    let source = "
+        data:
+            DB 0b11110000, 0x90, \"hi\"
+            DW 0x1234, data + 2
+    ";
+    assert_eq!(bytes(source), [0xF0, 0x90, b'h', b'i', 0x12, 0x34, 0x02, 0x02]);
+}
+
+#[test]
+fn includes_are_relative_to_the_including_file() {
+    let dir = common::temp_path("asm");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    std::fs::write(dir.join("lib/sprites.asm"), "INCLUDE \"more.asm\"\nsprite: DB 0xFF\n").unwrap();
+    std::fs::write(dir.join("lib/more.asm"), "CLS\n").unwrap();
+    let main = dir.join("main.asm");
+    let rom = assemble("LD I, sprite\nINCLUDE \"lib/sprites.asm\"\n", &main).unwrap();
+    assert_eq!(rom, [0xA2, 0x04, 0x00, 0xE0, 0xFF]);
+    // Errors in an included file name that file
+    std::fs::write(dir.join("lib/more.asm"), "CLS\nLD V0, 0x100\n").unwrap();
+    let error = assemble("INCLUDE \"lib/more.asm\"\n", &main).unwrap_err();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(error.file.ends_with("more.asm"), "{}", error);
+    assert_eq!(error.line, 2);
+}
+
+#[test]
+fn errors_point_at_their_line() {
+    let error = assemble("CLS\nADD V0, 1\nJP nowhere\n", Path::new("game.asm")).unwrap_err();
+    assert_eq!((error.file.as_str(), error.line), ("game.asm", 3));
+    assert!(error.to_string().starts_with("game.asm:3: "), "{}", error);
+    let error = assemble("CLS\nLD V0, 9223372036854775807 + 9223372036854775807\n", Path::new("game.asm")).unwrap_err();
+    assert_eq!(error.line, 2);
+    assert!(error.message.starts_with("value out of range"), "{}", error);
+    let error = assemble("CLS\n\nFROB V0\n", Path::new("game.asm")).unwrap_err();
+    assert_eq!(error.line, 3);
+}
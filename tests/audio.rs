@@ -0,0 +1,90 @@
+use chip_8::audio::{Tone, Voice, Waveform};
+use chip_8::cpu::{CHIP8, Config};
+use chip_8::snapshot::Snapshot;
+
+// Pulls samples from the tone generator directly, without an audio device.
+
+const RATE: u32 = 44_100;
+
+fn voice(on: bool, waveform: Waveform) -> Voice {
+    Voice { on, frequency: 441.0, waveform, volume: 0.5, pattern: None }
+}
+
+fn samples(tone: &mut Tone, count: usize) -> Vec<f32> {
+    tone.by_ref().take(count).collect()
+}
+
+#[test]
+fn waveform_names() {
+    assert_eq!(Waveform::from_name("Sine"), Some(Waveform::Sine));
+    assert_eq!(Waveform::from_name("triangle"), Some(Waveform::Triangle));
+    assert_eq!(Waveform::from_name("square"), Some(Waveform::Square));
+    assert_eq!(Waveform::from_name("sawtooth"), None);
+}
+
+#[test]
+fn tone_plays_at_its_frequency_and_volume() {
+    let mut tone = Tone::new(RATE);
+    tone.control().set(voice(true, Waveform::Square));
+    // Skipping the fade in
+    samples(&mut tone, RATE as usize / 10);
+    let second = samples(&mut tone, RATE as usize);
+    let flips = second.windows(2).filter(|pair| pair[0].signum() != pair[1].signum()).count();
+    assert!((880..=884).contains(&flips), "{} sign changes", flips);
+    assert!(second.iter().all(|sample| (sample.abs() - 0.5).abs() < 1e-6));
+}
+
+#[test]
+fn switching_fades_without_clicks() {
+    for waveform in [Waveform::Sine, Waveform::Triangle] {
+        let mut tone = Tone::new(RATE);
+        let control = tone.control();
+        assert!(samples(&mut tone, 1000).iter().all(|&sample| sample == 0.0));
+        // On for one 60Hz frame, then off again
+        control.set(voice(true, waveform));
+        let mut played = samples(&mut tone, RATE as usize / 60);
+        control.set(voice(false, waveform));
+        played.extend(samples(&mut tone, 1000));
+        assert!(played.iter().any(|sample| sample.abs() > 0.45));
+        // A 441Hz wave at half volume moves at most about 0.06 between samples
+        let jump = played.windows(2).map(|pair| (pair[1] - pair[0]).abs()).fold(0.0, f32::max);
+        assert!(jump < 0.07, "{:?} jumps by {}", waveform, jump);
+        assert!(played[played.len() - 100..].iter().all(|&sample| sample == 0.0));
+    }
+}
+
+#[test]
+fn xo_chip_pattern_replaces_the_tone() {
+    let mut tone = Tone::new(RATE);
+    // Eight bits high then eight bits low, at one bit per sample
+    let pattern = [0xFF, 0x00].repeat(8).try_into().unwrap();
+    tone.control().set(Voice { pattern: Some((pattern, RATE as f32)), ..voice(true, Waveform::Sine) });
+    samples(&mut tone, 128 * 20);
+    let played = samples(&mut tone, 128);
+    let start = played.iter().position(|&sample| sample > 0.0).unwrap();
+    let expected: Vec<f32> = (0..16).map(|bit| if (start + bit) % 16 < 8 { 0.5 } else { -0.5 }).collect();
+    assert_eq!(&played[..16], &expected[..]);
+}
+
+#[test]
+fn voice_follows_the_sound_timer_and_config() {
+    let config = Config { beep_frequency: 220.0, waveform: Waveform::Triangle, volume: 40, ..Config::default() };
+    let mut chip = CHIP8::with_config(config);
+    assert!(!Voice::of(&chip, true).on);
+    chip.sound_timer = 5;
+    let voice = Voice::of(&chip, true);
+    assert_eq!((voice.on, voice.frequency, voice.waveform, voice.volume), (true, 220.0, Waveform::Triangle, 0.4));
+    assert!(!Voice::of(&chip, false).on);
+    chip.config.mute = true;
+    assert!(!Voice::of(&chip, true).on);
+}
+
+#[test]
+fn save_states_keep_the_sound_settings() {
+    let config = Config { waveform: Waveform::Sine, volume: 80, mute: true, ..Config::default() };
+    let chip = CHIP8::with_config(config);
+    let snapshot = Snapshot::from_bytes(&chip.snapshot().to_bytes()).unwrap();
+    let mut restored = CHIP8::new();
+    restored.restore(&snapshot);
+    assert_eq!(restored.config, config);
+}
@@ -0,0 +1,18 @@
+// Helpers shared by the integration tests. Each test file uses only some of
+// them.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use chip_8::cpu::{CHIP8, Config};
+
+// Breakout on the original CHIP-8, with a fixed seed so runs repeat
+pub fn breakout(seed: u64) -> CHIP8 {
+    let mut chip = CHIP8::with_config(Config { seed: Some(seed), ..Config::preset("chip8").unwrap() });
+    chip.load_program("ROMS/Breakout.ch8").unwrap();
+    chip
+}
+
+// A file in the temporary directory that no other test process uses
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chip-8-{}-{}", std::process::id(), name))
+}
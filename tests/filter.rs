@@ -0,0 +1,72 @@
+mod common;
+
+use chip_8::display::Display;
+use chip_8::filter::{DisplayFilter, Filter, Glow, LEVELS};
+
+// Runs the display filters over a single pixel switching on and off.
+
+fn lit(on: bool) -> Display {
+    let mut display = Display::default();
+    if on {
+        display.set_pixel(2, 1, 1);
+    }
+    display
+}
+
+#[test]
+fn parse_filters() {
+    assert_eq!(Filter::parse("off"), Some(Filter::Off));
+    assert_eq!(Filter::parse(" Merge "), Some(Filter::Merge));
+    assert_eq!(Filter::parse("phosphor"), Some(Filter::Phosphor { decay: 50 }));
+    assert_eq!(Filter::parse("phosphor:75"), Some(Filter::Phosphor { decay: 75 }));
+    assert_eq!(Filter::parse("phosphor:100"), None);
+    assert_eq!(Filter::parse("merge:2"), None);
+    assert_eq!(Filter::parse("blur"), None);
+}
+
+#[test]
+fn merge_keeps_the_last_frame_lit() {
+    let mut filter = DisplayFilter::new(Filter::Merge);
+    filter.update(&lit(true));
+    assert!(!filter.update(&lit(false)));
+    assert_eq!(filter.glow(2, 1).planes, 1);
+    assert!(filter.update(&lit(false)));
+    assert_eq!(filter.glow(2, 1).planes, 0);
+}
+
+#[test]
+fn phosphor_fades_out() {
+    let mut filter = DisplayFilter::new(Filter::Phosphor { decay: 50 });
+    filter.update(&lit(true));
+    assert_eq!(filter.glow(2, 1), Glow { level: LEVELS - 1, planes: 1 });
+    let mut levels = Vec::new();
+    while filter.update(&lit(false)) {
+        levels.push(filter.glow(2, 1).level);
+    }
+    assert_eq!(levels, [7, 3, 1, 0]);
+    assert_eq!(filter.glow(2, 1).planes, 0);
+    // Turning back on is immediate
+    assert!(filter.update(&lit(true)));
+    assert_eq!(filter.glow(2, 1).level, LEVELS - 1);
+}
+
+#[test]
+fn resolution_change_starts_over() {
+    let mut filter = DisplayFilter::new(Filter::Phosphor { decay: 90 });
+    filter.update(&lit(true));
+    let mut hires = Display::default();
+    hires.set_hires(true);
+    filter.update(&hires);
+    assert_eq!((filter.width(), filter.height()), (128, 64));
+    assert_eq!(filter.glow(2, 1), Glow::default());
+}
+
+#[test]
+fn load_skips_comments() {
+    let path = common::temp_path("test.filter");
+    std::fs::write(&path, "; trails for the paddle\nphosphor:60\n").unwrap();
+    assert_eq!(Filter::load(&path).unwrap(), Filter::Phosphor { decay: 60 });
+    std::fs::write(&path, "sharpen\n").unwrap();
+    assert!(Filter::load(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
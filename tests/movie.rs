@@ -0,0 +1,68 @@
+mod common;
+
+use chip_8::cpu::CHIP8;
+use chip_8::input::{Command, Input};
+use chip_8::movie::{Movie, Recorder};
+
+// Records a session of Breakout through the movie Recorder and plays it back
+// headless, the way `--record` and `--play --headless` do.
+
+const FRAMES: u32 = 180;
+
+fn machine() -> CHIP8 {
+    common::breakout(11)
+}
+
+// Moves the paddle right, then left, then right again
+struct Script {
+    frame: u32,
+}
+
+impl Input for Script {
+    fn poll(&mut self, keypad: &mut [bool; 16]) -> Vec<Command> {
+        *keypad = [false; 16];
+        keypad[if (40..100).contains(&self.frame) { 0x4 } else { 0x6 }] = true;
+        self.frame += 1;
+        Vec::new()
+    }
+}
+
+fn record() -> Movie {
+    let mut chip = machine();
+    let mut script = Script { frame: 0 };
+    let mut recorder = Recorder::new(&mut script, Movie::new(&chip));
+    for _ in 0..FRAMES {
+        recorder.poll(&mut chip.keypad);
+        chip.run_frame().unwrap();
+    }
+    recorder.finish(&chip.display)
+}
+
+#[test]
+fn recordings_survive_saving() {
+    let movie = record();
+    assert_eq!(movie.frames.len(), FRAMES as usize);
+    assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+}
+
+#[test]
+fn playback_reproduces_the_recording() {
+    let movie = Movie::from_bytes(&record().to_bytes()).unwrap();
+    let mut chip = machine();
+    movie.check_rom(&chip).unwrap();
+    let mut frames = 0;
+    let matched = movie.play(&mut chip, |_| {
+        frames += 1;
+        Ok(())
+    });
+    assert!(matched.unwrap());
+    assert_eq!(frames, FRAMES);
+}
+
+#[test]
+fn changed_keys_fail_to_verify() {
+    let mut movie = record();
+    // Letting go of the paddle for a frame leaves it a step behind
+    movie.frames[60] = 0;
+    assert!(!movie.play(&mut machine(), |_| Ok(())).unwrap());
+}
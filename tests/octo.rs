@@ -0,0 +1,123 @@
+use std::path::Path;
+use chip_8::error::AsmError;
+use chip_8::octo::compile;
+
+// Compiles small Octo programs and checks the bytes they turn into. Programs
+// start with `: main` so no jump to it is placed in front.
+
+fn words(source: &str) -> Vec<u16> {
+    let rom = compile(source, Path::new("test.8o")).unwrap();
+    rom.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+fn error(source: &str) -> AsmError {
+    compile(source, Path::new("test.8o")).unwrap_err()
+}
+
+#[test]
+fn main_elsewhere_gets_a_jump() {
+    assert_eq!(words(": sub return\n: main sub"), [0x1204, 0x00EE, 0x2202]);
+}
+
+#[test]
+fn loop_again_jumps_back() {
+    assert_eq!(words(": main clear loop v0 += 1 again"), [0x00E0, 0x7001, 0x1202]);
+}
+
+#[test]
+fn while_leaves_the_loop() {
+    // The skip passes over the jump out while v0 is not 5
+    assert_eq!(words(": main loop v0 += 1 while v0 != 5 again clear"), [0x7001, 0x4005, 0x1208, 0x1200, 0x00E0]);
+}
+
+#[test]
+fn if_then_skips_one_statement() {
+    assert_eq!(words(": main if v0 == 3 then v1 := 2"), [0x4003, 0x6102]);
+    assert_eq!(words(": main if v0 key then clear"), [0xE0A1, 0x00E0]);
+}
+
+#[test]
+fn if_begin_else_end() {
+    let program = ": main if v0 == v1 begin v2 := 1 else v2 := 2 end clear";
+    assert_eq!(words(program), [0x5010, 0x1208, 0x6201, 0x120A, 0x6202, 0x00E0]);
+    assert_eq!(words(": main if v3 != 7 begin v2 := 1 end"), [0x4307, 0x1206, 0x6201]);
+}
+
+#[test]
+fn comparisons_use_vf() {
+    // vf := 9, vf =- v0, then skip unless there was no borrow
+    assert_eq!(words(": main if v0 >= 9 then clear"), [0x6F09, 0x8F07, 0x4F01, 0x00E0]);
+}
+
+#[test]
+fn macros_substitute_their_arguments() {
+    let program = ": main\n:macro twice reg { reg += 1 reg += 1 }\ntwice v3 twice v4";
+    assert_eq!(words(program), [0x7301, 0x7301, 0x7401, 0x7401]);
+}
+
+#[test]
+fn calc_evaluates_right_to_left() {
+    assert_eq!(words(": main :calc size { 2 * 4 + 1 } v0 := size"), [0x600A]);
+}
+
+#[test]
+fn calc_remainders_keep_their_divisor() {
+    assert_eq!(words(": main :calc x { 7 % -2 } v0 := x"), [0x6001]);
+    let error = error(": main\n:calc x { 7 % 0 }");
+    assert_eq!((error.line, error.message.as_str()), (2, "remainder of a division by zero in calculation"));
+}
+
+#[test]
+fn calc_shifts_out_of_range_are_errors() {
+    assert_eq!(words(": main :calc x { 3 << 4 } v0 := x"), [0x6030]);
+    assert_eq!(error(": main\n:calc x { 1 << 70 }").message, "cannot shift by 70 in calculation");
+    assert_eq!(error(": main\n:calc x { 8 >> -1 }").message, "cannot shift by -1 in calculation");
+}
+
+#[test]
+fn aliases_and_constants() {
+    assert_eq!(words(": main :alias x v5 :const SPEED 3 x := 7 x += SPEED"), [0x6507, 0x7503]);
+}
+
+#[test]
+fn unpack_splits_an_address() {
+    let rom = compile(": main :unpack 0xA data return : data 0x12", Path::new("test.8o")).unwrap();
+    assert_eq!(rom, [0x60, 0xA2, 0x61, 0x06, 0x00, 0xEE, 0x12]);
+    let rom = compile(": main :unpack long data : data 0x34", Path::new("test.8o")).unwrap();
+    assert_eq!(rom, [0x60, 0x02, 0x61, 0x04, 0x34]);
+}
+
+#[test]
+fn next_labels_the_following_operand() {
+    assert_eq!(words(": main :next speed v0 := 5 i := speed"), [0x6005, 0xA201]);
+}
+
+#[test]
+fn unmatched_blocks_report_their_line() {
+    let unmatched = error(": main\nclear\nagain");
+    assert_eq!((unmatched.line, unmatched.message.as_str()), (3, "'again' without a matching 'loop'"));
+    let unmatched = error(": main\nend");
+    assert_eq!((unmatched.line, unmatched.message.as_str()), (2, "'end' without a matching 'if ... begin'"));
+    assert_eq!(error(": main\nloop\nclear").message, "missing 'end' or 'again' at the end of the program");
+}
+
+#[test]
+fn jumps_beyond_4kb_are_errors() {
+    let labelled = error(": main\njump far\n:org 0x1000\n: far clear");
+    assert_eq!(labelled.line, 2);
+    assert!(labelled.message.contains("beyond the 4KB"), "{}", labelled.message);
+    // Loops and blocks compile to jumps of their own
+    let looped = error(": main\n:org 0x1234\nloop\nv0 += 1\nagain");
+    assert_eq!(looped.line, 5);
+    assert!(looped.message.contains("beyond the 4KB"), "{}", looped.message);
+    let block = error(": main\n:org 0xFFC\nif v0 == 1 begin\nclear\nend");
+    assert_eq!(block.line, 5);
+    assert!(block.message.contains("beyond the 4KB"), "{}", block.message);
+}
+
+#[test]
+fn unknown_identifiers_are_errors() {
+    let unknown = error(": main\nclear\nfoo");
+    assert_eq!((unknown.line, unknown.message.as_str()), (3, "undefined name 'foo'"));
+    assert_eq!(unknown.to_string(), "test.8o:3: undefined name 'foo'");
+}
@@ -0,0 +1,570 @@
+use chip_8::cpu::{BIG_FONT_START, CHIP8, Config, FONT_START, STACK_SIZE};
+use chip_8::error::Chip8Error;
+
+// One or more tests for every arm of the instruction match in `CHIP8::execute`,
+// covering the flags and the quirk and platform variations.
+
+// A machine with the preset's settings and `program` loaded at 0x200
+fn machine(preset: &str, program: &[u16]) -> CHIP8 {
+    let mut chip = CHIP8::with_config(Config { seed: Some(1), ..Config::preset(preset).unwrap() });
+    let rom: Vec<u8> = program.iter().flat_map(|word| word.to_be_bytes()).collect();
+    chip.load_rom(&rom).unwrap();
+    chip
+}
+
+fn run(chip: &mut CHIP8, steps: usize) {
+    for _ in 0..steps {
+        chip.step().unwrap();
+    }
+}
+
+// Runs the instructions of a plain CHIP-8 program
+fn chip8(program: &[u16], steps: usize) -> CHIP8 {
+    let mut chip = machine("chip8", program);
+    run(&mut chip, steps);
+    chip
+}
+
+fn lit(chip: &CHIP8) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for y in 0..chip.display.height() {
+        for x in 0..chip.display.width() {
+            if chip.display.get(x, y) {
+                pixels.push((x, y));
+            }
+        }
+    }
+    pixels
+}
+
+// 00E0 - 00FF
+
+#[test]
+fn clear_screen() {
+    let mut chip = machine("chip8", &[0x00E0]);
+    chip.display.set_pixel(3, 4, 1);
+    run(&mut chip, 1);
+    assert!(lit(&chip).is_empty());
+}
+
+#[test]
+fn call_and_return() {
+    // 0x200 calls 0x206, which returns to 0x202
+    let chip = chip8(&[0x2206, 0x0000, 0x0000, 0x00EE], 2);
+    assert_eq!(chip.pc, 0x202);
+    assert!(chip.stack.is_empty());
+}
+
+#[test]
+fn return_with_empty_stack() {
+    let mut chip = machine("chip8", &[0x00EE]);
+    assert!(matches!(chip.step(), Err(Chip8Error::StackUnderflow { addr: 0x200 })));
+    assert_eq!(chip.pc, 0x200);
+}
+
+#[test]
+fn call_stack_overflow() {
+    let mut chip = machine("chip8", &[0x2200]);
+    run(&mut chip, STACK_SIZE);
+    assert!(matches!(chip.step(), Err(Chip8Error::StackOverflow { addr: 0x200 })));
+}
+
+#[test]
+fn scroll_down() {
+    let mut chip = machine("schip1.1", &[0x00C3]);
+    chip.display.set_pixel(5, 1, 1);
+    run(&mut chip, 1);
+    assert_eq!(lit(&chip), vec![(5, 4)]);
+}
+
+#[test]
+fn scroll_up() {
+    let mut chip = machine("xochip", &[0x00D2]);
+    chip.display.set_pixel(5, 3, 1);
+    run(&mut chip, 1);
+    assert_eq!(lit(&chip), vec![(5, 1)]);
+}
+
+#[test]
+fn scroll_right_and_left() {
+    let mut chip = machine("schip1.1", &[0x00FB, 0x00FC, 0x00FC]);
+    chip.display.set_pixel(10, 0, 1);
+    run(&mut chip, 1);
+    assert_eq!(lit(&chip), vec![(14, 0)]);
+    run(&mut chip, 2);
+    assert_eq!(lit(&chip), vec![(6, 0)]);
+}
+
+#[test]
+fn exit_stays_on_the_instruction() {
+    let mut chip = machine("schip1.1", &[0x00FD]);
+    assert!(chip.step().unwrap().exited);
+    assert_eq!(chip.pc, 0x200);
+}
+
+#[test]
+fn resolution_switch() {
+    let mut chip = machine("schip1.1", &[0x00FF, 0x00FE]);
+    run(&mut chip, 1);
+    assert_eq!((chip.display.width(), chip.display.height()), (128, 64));
+    run(&mut chip, 1);
+    assert_eq!((chip.display.width(), chip.display.height()), (64, 32));
+}
+
+#[test]
+fn extended_opcodes_are_unknown_on_chip8() {
+    for opcode in [0x00C1, 0x00D1, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0x0123] {
+        let mut chip = machine("chip8", &[opcode]);
+        assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { .. })), "{:04X}", opcode);
+    }
+}
+
+// 1NNN - 7XNN
+
+#[test]
+fn jump() {
+    assert_eq!(chip8(&[0x1ABC], 1).pc, 0xABC);
+}
+
+#[test]
+fn call_pushes_the_return_address() {
+    let chip = chip8(&[0x2ABC], 1);
+    assert_eq!(chip.pc, 0xABC);
+    assert_eq!(chip.stack, vec![0x202]);
+}
+
+#[test]
+fn skip_if_equal_immediate() {
+    assert_eq!(chip8(&[0x6012, 0x3012], 2).pc, 0x206);
+    assert_eq!(chip8(&[0x6012, 0x3013], 2).pc, 0x204);
+}
+
+#[test]
+fn skip_if_not_equal_immediate() {
+    assert_eq!(chip8(&[0x6012, 0x4013], 2).pc, 0x206);
+    assert_eq!(chip8(&[0x6012, 0x4012], 2).pc, 0x204);
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    assert_eq!(chip8(&[0x6005, 0x6105, 0x5010], 3).pc, 0x208);
+    assert_eq!(chip8(&[0x6005, 0x6106, 0x5010], 3).pc, 0x206);
+}
+
+#[test]
+fn register_skips_with_a_low_nibble_are_unknown() {
+    for (preset, opcodes) in [("chip8", &[0x5011, 0x5012, 0x5013, 0x501F, 0x9011][..]), ("xochip", &[0x5011, 0x5014, 0x901F])] {
+        for &opcode in opcodes {
+            let mut chip = machine(preset, &[opcode]);
+            assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { .. })), "{} {:04X}", preset, opcode);
+        }
+    }
+}
+
+#[test]
+fn skip_over_long_load_on_xo_chip() {
+    let mut chip = machine("xochip", &[0x3000, 0xF000, 0x1234]);
+    run(&mut chip, 1);
+    assert_eq!(chip.pc, 0x206);
+}
+
+#[test]
+fn save_and_load_register_range() {
+    // V1-V3 saved to 0x300 in order, then loaded back reversed into V3-V1
+    let mut chip = machine("xochip", &[0x6101, 0x6202, 0x6303, 0xA300, 0x5132, 0x5313]);
+    run(&mut chip, 5);
+    assert_eq!(chip.memory[0x300..0x303], [1, 2, 3]);
+    assert_eq!(chip.index, 0x300);
+    run(&mut chip, 1);
+    assert_eq!(chip.variables[1..4], [3, 2, 1]);
+}
+
+#[test]
+fn load_immediate() {
+    assert_eq!(chip8(&[0x6A42], 1).variables[0xA], 0x42);
+}
+
+#[test]
+fn add_immediate_wraps_without_flag() {
+    let chip = chip8(&[0x60FF, 0x6F05, 0x7002], 3);
+    assert_eq!(chip.variables[0], 0x01);
+    assert_eq!(chip.variables[0xF], 0x05);
+}
+
+// 8XY0 - 8XYE
+
+#[test]
+fn copy_register() {
+    assert_eq!(chip8(&[0x6107, 0x8010], 2).variables[0], 7);
+}
+
+#[test]
+fn bitwise_operations() {
+    let chip = chip8(&[0x600C, 0x610A, 0x8011], 3);
+    assert_eq!(chip.variables[0], 0x0E);
+    let chip = chip8(&[0x600C, 0x610A, 0x8012], 3);
+    assert_eq!(chip.variables[0], 0x08);
+    let chip = chip8(&[0x600C, 0x610A, 0x8013], 3);
+    assert_eq!(chip.variables[0], 0x06);
+}
+
+#[test]
+fn bitwise_operations_reset_flag_on_vip() {
+    for opcode in [0x8011, 0x8012, 0x8013] {
+        let mut chip = machine("vip", &[0x6F05, opcode]);
+        run(&mut chip, 2);
+        assert_eq!(chip.variables[0xF], 0, "{:04X}", opcode);
+        let chip = chip8(&[0x6F05, opcode], 2);
+        assert_eq!(chip.variables[0xF], 5, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn add_sets_carry() {
+    let chip = chip8(&[0x60F0, 0x6120, 0x8014], 3);
+    assert_eq!(chip.variables[0], 0x10);
+    assert_eq!(chip.variables[0xF], 1);
+}
+
+#[test]
+fn add_clears_carry() {
+    let chip = chip8(&[0x6F01, 0x6010, 0x6120, 0x8014], 4);
+    assert_eq!(chip.variables[0], 0x30);
+    assert_eq!(chip.variables[0xF], 0);
+}
+
+#[test]
+fn flag_wins_over_result_in_vf() {
+    // VF + V1 = 0x30 with no carry, and the flag is written last
+    let chip = chip8(&[0x6F10, 0x6120, 0x8F14], 3);
+    assert_eq!(chip.variables[0xF], 0);
+    let chip = chip8(&[0x6F10, 0x6120, 0x8F15], 3);
+    assert_eq!(chip.variables[0xF], 0);
+}
+
+#[test]
+fn subtract() {
+    let chip = chip8(&[0x6030, 0x6110, 0x8015], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x20, 1));
+    let chip = chip8(&[0x6010, 0x6130, 0x8015], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0xE0, 0));
+    // Equal values do not borrow
+    let chip = chip8(&[0x6010, 0x6110, 0x8015], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x00, 1));
+}
+
+#[test]
+fn subtract_reversed() {
+    let chip = chip8(&[0x6010, 0x6130, 0x8017], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x20, 1));
+    let chip = chip8(&[0x6030, 0x6110, 0x8017], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0xE0, 0));
+}
+
+#[test]
+fn shift_right() {
+    let chip = chip8(&[0x6005, 0x6108, 0x8016], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x02, 1));
+    // The VIP shifts Vy into Vx
+    let mut chip = machine("vip", &[0x6005, 0x6108, 0x8016]);
+    run(&mut chip, 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x04, 0));
+}
+
+#[test]
+fn shift_left() {
+    let chip = chip8(&[0x6081, 0x6101, 0x801E], 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x02, 1));
+    let mut chip = machine("vip", &[0x6081, 0x6101, 0x801E]);
+    run(&mut chip, 3);
+    assert_eq!((chip.variables[0], chip.variables[0xF]), (0x02, 0));
+}
+
+#[test]
+fn unknown_arithmetic_opcode() {
+    let mut chip = machine("chip8", &[0x8018]);
+    assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { addr: 0x200, opcode: 0x8018 })));
+}
+
+// 9XY0 - DXYN
+
+#[test]
+fn skip_if_registers_not_equal() {
+    assert_eq!(chip8(&[0x6005, 0x6106, 0x9010], 3).pc, 0x208);
+    assert_eq!(chip8(&[0x6005, 0x6105, 0x9010], 3).pc, 0x206);
+}
+
+#[test]
+fn load_index() {
+    assert_eq!(chip8(&[0xA123], 1).index, 0x123);
+}
+
+#[test]
+fn jump_with_offset() {
+    assert_eq!(chip8(&[0x6004, 0x6302, 0xB300], 3).pc, 0x304);
+    // CHIP-48 adds Vx, where X is the top nibble of the address
+    let mut chip = machine("chip48", &[0x6004, 0x6302, 0xB300]);
+    run(&mut chip, 3);
+    assert_eq!(chip.pc, 0x302);
+}
+
+#[test]
+fn random_is_masked_and_seeded() {
+    let chip = chip8(&[0xC00F, 0xC1F0], 2);
+    assert_eq!(chip.variables[0] & 0xF0, 0);
+    assert_eq!(chip.variables[1] & 0x0F, 0);
+    let again = chip8(&[0xC00F, 0xC1F0], 2);
+    assert_eq!(chip.variables, again.variables);
+}
+
+#[test]
+fn draw_and_collide() {
+    // An 8x2 sprite at (1, 2): a row of four pixels, then one pixel
+    let program = [0xA20A, 0x6001, 0x6102, 0xD012, 0xD012, 0xF080];
+    let mut chip = machine("chip8", &program);
+    run(&mut chip, 4);
+    assert_eq!(lit(&chip), vec![(1, 2), (2, 2), (3, 2), (4, 2), (1, 3)]);
+    assert_eq!(chip.variables[0xF], 0);
+    // Drawing it again erases it and reports the collision
+    run(&mut chip, 1);
+    assert!(lit(&chip).is_empty());
+    assert_eq!(chip.variables[0xF], 1);
+}
+
+#[test]
+fn draw_clips_or_wraps_at_the_edges() {
+    // One row of eight pixels starting two pixels from the right edge
+    let program = [0xA208, 0x603E, 0x6100, 0xD011, 0xFF00];
+    let mut chip = machine("chip8", &program);
+    run(&mut chip, 4);
+    assert_eq!(lit(&chip), vec![(62, 0), (63, 0)]);
+    let mut chip = machine("xochip", &program);
+    run(&mut chip, 4);
+    assert_eq!(lit(&chip).len(), 8);
+    assert!(chip.display.get(0, 0) && chip.display.get(5, 0));
+}
+
+#[test]
+fn schip11_counts_colliding_rows_in_hires() {
+    // The 5 row font zero drawn twice at the top left, then once more with
+    // three of its rows below the bottom edge
+    let program = [0x00FF, 0x6000, 0x6100, 0xF029, 0xD015, 0xD015, 0x613E, 0xD015];
+    let mut chip = machine("schip1.1", &program);
+    run(&mut chip, 6);
+    assert_eq!(chip.variables[0xF], 5);
+    run(&mut chip, 2);
+    assert_eq!(chip.variables[0xF], 3);
+    // SUPER-CHIP 1.0 only sets VF for the collision
+    let mut chip = machine("schip1.0", &program);
+    run(&mut chip, 6);
+    assert_eq!(chip.variables[0xF], 1);
+    run(&mut chip, 2);
+    assert_eq!(chip.variables[0xF], 0);
+    // and so does SUPER-CHIP 1.1 in lores mode
+    let mut chip = machine("schip1.1", &program[1..]);
+    run(&mut chip, 5);
+    assert_eq!(chip.variables[0xF], 1);
+}
+
+#[test]
+fn draw_start_position_wraps() {
+    // Coordinates past the edge wrap before drawing starts
+    let program = [0xA208, 0x6042, 0x6121, 0xD011, 0x8000];
+    let chip = chip8(&program, 4);
+    assert_eq!(lit(&chip), vec![(2, 1)]);
+}
+
+#[test]
+fn draw_waits_for_vblank_on_vip() {
+    let program = [0xA20A, 0x6000, 0x6100, 0xD011, 0xD011, 0x8000];
+    let mut chip = machine("vip", &program);
+    chip.tick_timers();
+    run(&mut chip, 4);
+    let step = chip.step().unwrap();
+    assert!(step.waiting_for_vblank);
+    assert_eq!(chip.pc, 0x208);
+    chip.tick_timers();
+    assert!(!chip.step().unwrap().waiting_for_vblank);
+    assert!(lit(&chip).is_empty());
+}
+
+#[test]
+fn draw_large_sprite() {
+    let mut program = vec![0x00FF, 0xA20A, 0x6000, 0x6100, 0xD010];
+    program.extend([0xFFFF; 16]);
+    let mut chip = machine("schip1.1", &program);
+    run(&mut chip, 5);
+    assert_eq!(lit(&chip).len(), 256);
+    assert!(chip.display.get(15, 15));
+}
+
+#[test]
+fn draw_on_selected_planes() {
+    // A pixel on plane 2 alone, then one row below on both planes, where
+    // each plane takes its own byte of sprite data
+    let program = [0xF201, 0xA20C, 0xD001, 0xF301, 0x6101, 0xD011, 0x8040];
+    let mut chip = machine("xochip", &program);
+    run(&mut chip, 3);
+    assert_eq!(chip.display.pixel(0, 0), 2);
+    run(&mut chip, 3);
+    assert_eq!(chip.display.pixel(0, 0), 2);
+    assert_eq!(chip.display.pixel(0, 1), 1);
+    assert_eq!(chip.display.pixel(1, 1), 2);
+}
+
+#[test]
+fn draw_reads_past_the_end_of_memory() {
+    let mut chip = machine("chip8", &[0xAFFF, 0xD002]);
+    run(&mut chip, 1);
+    assert!(matches!(chip.step(), Err(Chip8Error::MemoryOutOfRange { addr: 0x202, .. })));
+}
+
+// EX9E - EXA1
+
+#[test]
+fn skip_if_key_pressed() {
+    let mut chip = machine("chip8", &[0x6007, 0xE09E]);
+    chip.keypad[7] = true;
+    run(&mut chip, 2);
+    assert_eq!(chip.pc, 0x206);
+    assert_eq!(chip8(&[0x6007, 0xE09E], 2).pc, 0x204);
+}
+
+#[test]
+fn skip_if_key_not_pressed() {
+    let mut chip = machine("chip8", &[0x6007, 0xE0A1]);
+    chip.keypad[7] = true;
+    run(&mut chip, 2);
+    assert_eq!(chip.pc, 0x204);
+    assert_eq!(chip8(&[0x6007, 0xE0A1], 2).pc, 0x206);
+}
+
+#[test]
+fn unknown_key_opcode() {
+    let mut chip = machine("chip8", &[0xE000]);
+    assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { .. })));
+}
+
+// FX07 - FX85
+
+#[test]
+fn long_index_load() {
+    let mut chip = machine("xochip", &[0xF000, 0xBEEF]);
+    run(&mut chip, 1);
+    assert_eq!(chip.index, 0xBEEF);
+    assert_eq!(chip.pc, 0x204);
+}
+
+#[test]
+fn audio_pattern_and_pitch() {
+    let mut program = vec![0xA208, 0xF002, 0x6080, 0xF03A];
+    program.extend([0xAAAA; 8]);
+    let mut chip = machine("xochip", &program);
+    run(&mut chip, 4);
+    assert_eq!(chip.audio_pattern, Some([0xAA; 16]));
+    assert_eq!(chip.pitch, 0x80);
+}
+
+#[test]
+fn xo_chip_opcodes_are_unknown_elsewhere() {
+    for opcode in [0xF000, 0xF101, 0xF002, 0xF03A] {
+        let mut chip = machine("schip1.1", &[opcode]);
+        assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { .. })), "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn timers() {
+    let mut chip = machine("chip8", &[0x6009, 0xF015, 0xF018, 0xF107]);
+    run(&mut chip, 3);
+    assert_eq!((chip.delay_timer, chip.sound_timer), (9, 9));
+    chip.tick_timers();
+    run(&mut chip, 1);
+    assert_eq!(chip.variables[1], 8);
+    assert_eq!(chip.sound_timer, 8);
+}
+
+#[test]
+fn add_to_index() {
+    assert_eq!(chip8(&[0xA100, 0x6020, 0xF01E], 3).index, 0x120);
+}
+
+#[test]
+fn wait_for_key() {
+    let mut chip = machine("chip8", &[0xF30A]);
+    assert!(chip.step().unwrap().waiting_for_key);
+    assert_eq!(chip.pc, 0x200);
+    chip.keypad[0xB] = true;
+    assert!(!chip.step().unwrap().waiting_for_key);
+    assert_eq!(chip.variables[3], 0xB);
+    assert_eq!(chip.pc, 0x202);
+}
+
+#[test]
+fn waiting_at_the_end_of_memory() {
+    // The pc has already wrapped to 0 when these instructions step back
+    for (opcode, step_back) in [(0xF30A_u16, true), (0x00FD, true), (0x6001, false)] {
+        let mut chip = machine("xochip", &[]);
+        chip.memory[0xFFFE..].copy_from_slice(&opcode.to_be_bytes());
+        chip.pc = 0xFFFE;
+        chip.step().unwrap();
+        assert_eq!(chip.pc, if step_back { 0xFFFE } else { 0 }, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn font_characters() {
+    assert_eq!(chip8(&[0x600A, 0xF029], 2).index, FONT_START + 50);
+    let mut chip = machine("schip1.1", &[0x6003, 0xF030]);
+    run(&mut chip, 2);
+    assert_eq!(chip.index, BIG_FONT_START + 30);
+}
+
+#[test]
+fn binary_coded_decimal() {
+    let chip = chip8(&[0x607B, 0xA300, 0xF033], 3);
+    assert_eq!(chip.memory[0x300..0x303], [1, 2, 3]);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let chip = chip8(&[0x6001, 0x6102, 0x6203, 0xA300, 0xF255, 0x6000, 0xF065], 7);
+    assert_eq!(chip.memory[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(chip.variables[0], 1);
+    assert_eq!(chip.index, 0x300);
+}
+
+#[test]
+fn store_and_load_move_the_index() {
+    for (preset, index) in [("chip8", 0x300), ("chip48", 0x302), ("vip", 0x303)] {
+        for opcode in [0xF255, 0xF265] {
+            let mut chip = machine(preset, &[0xA300, opcode]);
+            run(&mut chip, 2);
+            assert_eq!(chip.index, index, "{} {:04X}", preset, opcode);
+        }
+    }
+}
+
+#[test]
+fn store_past_the_end_of_memory() {
+    let mut chip = machine("chip8", &[0xAFFF, 0xF155]);
+    run(&mut chip, 1);
+    assert!(matches!(chip.step(), Err(Chip8Error::MemoryOutOfRange { addr: 0x202, .. })));
+}
+
+#[test]
+fn rpl_flags() {
+    let mut chip = machine("schip1.1", &[0x6005, 0x6106, 0xF175, 0x6000, 0x6100, 0xF185]);
+    run(&mut chip, 3);
+    assert_eq!(chip.rpl[..2], [5, 6]);
+    run(&mut chip, 3);
+    assert_eq!(chip.variables[..2], [5, 6]);
+}
+
+#[test]
+fn unknown_misc_opcode() {
+    for opcode in [0xF0FF, 0xF075, 0xF030] {
+        let mut chip = machine("chip8", &[opcode]);
+        assert!(matches!(chip.step(), Err(Chip8Error::UnknownOpcode { .. })), "{:04X}", opcode);
+    }
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use chip_8::palette::{nearest_xterm, xterm_rgb, Color, Palette};
+
+#[test]
+fn palette_names_and_colours() {
+    assert_eq!(Palette::parse("DEFAULT"), Some(Palette::default()));
+    assert!(Palette::parse("octo").is_some());
+    assert!(Palette::parse("gameboy").is_some());
+    let palette = Palette::parse("#000000, 102030").unwrap();
+    let color = Color::Rgb([0x10, 0x20, 0x30]);
+    assert_eq!(palette.colors, [Color::Rgb([0, 0, 0]), color, color, color]);
+    assert_eq!(Palette::parse("#000000"), None);
+    assert_eq!(Palette::parse("#000000,#12345G"), None);
+    assert_eq!(Palette::parse("sepia"), None);
+}
+
+#[test]
+fn palettes_mix_rgb_and_256_colour_entries() {
+    let palette = Palette::parse("16 #FF0000 231 244").unwrap();
+    assert_eq!(palette.colors, [Color::Indexed(16), Color::Rgb([0xFF, 0, 0]), Color::Indexed(231), Color::Indexed(244)]);
+    assert_eq!(palette.color(0), [0, 0, 0]);
+    assert_eq!(palette.color(2), [0xFF, 0xFF, 0xFF]);
+    assert_eq!(palette.color(3), [0x80, 0x80, 0x80]);
+    assert_eq!(Palette::parse("0,256"), None);
+}
+
+#[test]
+fn nearest_xterm_colours() {
+    assert_eq!(xterm_rgb(196), [0xFF, 0, 0]);
+    assert_eq!(nearest_xterm([0xFF, 0x10, 0x08], 16..=255), 196);
+    assert_eq!(nearest_xterm([0x30, 0x30, 0x30], 16..=255), 236);
+    assert_eq!(nearest_xterm([0x10, 0xF0, 0x20], 0..=15), 10);
+}
+
+#[test]
+fn palette_files_hold_a_colour_per_line() {
+    let path = common::temp_path("test.palette");
+    std::fs::write(&path, "; Dark on light\n#FFFFFF\n#000000\n\n").unwrap();
+    let palette = Palette::load(&path);
+    std::fs::write(&path, "sepia\n").unwrap();
+    let invalid = Palette::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(palette.unwrap().color(1), [0, 0, 0]);
+    assert!(invalid.is_err());
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use chip_8::cpu::{CHIP8, Run};
+use chip_8::rewind::Rewind;
+
+// Rewinding has to land on exactly the state the machine was in, so running
+// the same frames again with the same keys ends up where it did before.
+
+fn machine() -> CHIP8 {
+    common::breakout(3)
+}
+
+// Keys held in each frame: the paddle moving left and right
+fn keys(frame: u32) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    keypad[if frame % 40 < 20 { 0x4 } else { 0x6 }] = true;
+    keypad
+}
+
+// Runs a frame the way the frontend does, checkpointing first
+fn frame(chip: &mut CHIP8, history: &mut Rewind, frame: u32) {
+    chip.keypad = keys(frame);
+    history.checkpoint(chip);
+    let run = chip.run_frame().unwrap();
+    history.record(&run);
+}
+
+#[test]
+fn rewound_frames_replay_the_same() {
+    const FRAMES: u32 = 150;
+    const BACK: u32 = 45;
+    let mut chip = machine();
+    let mut history = Rewind::new(60);
+    let mut states = Vec::new();
+    for n in 0..FRAMES {
+        states.push(chip.snapshot());
+        frame(&mut chip, &mut history, n);
+    }
+    let end = chip.snapshot();
+
+    for _ in 0..BACK {
+        assert!(history.back_frame(&mut chip));
+    }
+    assert_eq!(chip.snapshot(), states[(FRAMES - BACK) as usize]);
+    assert_ne!(chip.snapshot(), end);
+    for n in FRAMES - BACK..FRAMES {
+        frame(&mut chip, &mut history, n);
+    }
+    assert_eq!(chip.snapshot(), end);
+}
+
+#[test]
+fn history_runs_out() {
+    let mut chip = machine();
+    let mut history = Rewind::new(10);
+    for n in 0..30 {
+        frame(&mut chip, &mut history, n);
+    }
+    assert_eq!(history.len(), 10);
+    for _ in 0..10 {
+        assert!(history.back_frame(&mut chip));
+    }
+    assert!(!history.back_frame(&mut chip));
+}
+
+#[test]
+fn stepping_back_undoes_one_instruction() {
+    let mut chip = machine();
+    let mut history = Rewind::new(60);
+    for n in 0..20 {
+        frame(&mut chip, &mut history, n);
+    }
+    // Running on into the next frame one instruction at a time
+    chip.keypad = keys(20);
+    history.checkpoint(&chip);
+    let mut states = Vec::new();
+    for _ in 0..5 {
+        states.push(chip.snapshot());
+        let step = chip.step().unwrap();
+        history.record(&Run { cycles: 1, last: Some(step), ..Run::default() });
+    }
+    for state in states.iter().rev() {
+        assert!(history.back_step(&mut chip));
+        assert_eq!(&chip.snapshot(), state);
+    }
+    // The step before that goes back into the previous frame
+    assert!(history.back_step(&mut chip));
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+use chip_8::cpu::{CHIP8, Config};
+
+// Runs the test ROMs bundled in ROMS/ headless and compares the final
+// framebuffer with a golden image in tests/golden, stored in the same text
+// form `--headless` prints: one line per row, `#` for a lit pixel.
+//
+// Set UPDATE_GOLDEN=1 to rewrite the golden images from the current output,
+// then check the new pictures by eye before committing them.
+
+// Frames to run, long enough for every ROM to finish drawing its results
+const FRAMES: u32 = 120;
+
+fn check(rom: &str, golden: &str) {
+    let config = Config { seed: Some(1), ..Config::preset("chip8").unwrap() };
+    let mut chip = CHIP8::with_config(config);
+    chip.load_program(format!("ROMS/{}", rom)).unwrap();
+    for _ in 0..FRAMES {
+        chip.run_frame().unwrap();
+    }
+    let actual = chip.display.to_string();
+
+    let path = PathBuf::from("tests/golden").join(golden);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap();
+    assert!(actual == expected, "{} drew\n{}\nbut {} expects\n{}", rom, actual, path.display(), expected);
+}
+
+#[test]
+fn test_opcode() {
+    check("test_opcode.ch8", "test_opcode.txt");
+}
+
+#[test]
+fn bc_test() {
+    check("bc_test.ch8", "bc_test.txt");
+}
+
+#[test]
+fn ibm_logo() {
+    check("IBM Logo.ch8", "ibm_logo.txt");
+}
+
+#[test]
+fn test_rom() {
+    check("Test.ch8", "test.txt");
+}
@@ -0,0 +1,101 @@
+use std::path::Path;
+use chip_8::display::Display;
+use chip_8::palette::Palette;
+use chip_8::screenshot::{encode, ImageFormat};
+
+// Checks the image encoders on a small display with one pixel of each
+// plane combination along the top row.
+
+fn display() -> Display {
+    let mut display = Display::default();
+    for planes in 0..4 {
+        display.set_pixel(planes as usize, 0, planes);
+    }
+    display
+}
+
+#[test]
+fn format_from_extension() {
+    assert_eq!(ImageFormat::from_path(Path::new("shot.PNG")), Some(ImageFormat::Png));
+    assert_eq!(ImageFormat::from_path(Path::new("shot.ppm")), Some(ImageFormat::Ppm));
+    assert_eq!(ImageFormat::from_path(Path::new("shot.pbm")), Some(ImageFormat::Pbm));
+    assert_eq!(ImageFormat::from_path(Path::new("shot.jpg")), None);
+    assert_eq!(ImageFormat::from_path(Path::new("shot")), None);
+}
+
+#[test]
+fn ppm_uses_the_palette_and_scale() {
+    let palette = Palette::parse("010101,020202,030303,040404").unwrap();
+    let image = encode(&display(), ImageFormat::Ppm, 2, &palette);
+    let header = b"P6\n128 64\n255\n";
+    assert_eq!(&image[..header.len()], header);
+    let pixels = &image[header.len()..];
+    assert_eq!(pixels.len(), 128 * 64 * 3);
+    // The first two image rows are both the top display row, two pixels per pixel
+    for row in 0..2 {
+        let start = row * 128 * 3;
+        let expected: Vec<u8> = [1, 1, 2, 2, 3, 3, 4, 4, 1].iter().flat_map(|&c| [c; 3]).collect();
+        assert_eq!(&pixels[start..start + expected.len()], &expected[..]);
+    }
+    assert!(pixels[2 * 128 * 3..].iter().all(|&c| c == 1));
+}
+
+#[test]
+fn pbm_packs_lit_pixels_as_white() {
+    let image = encode(&display(), ImageFormat::Pbm, 1, &Palette::default());
+    let header = b"P4\n64 32\n";
+    assert_eq!(&image[..header.len()], header);
+    let rows = &image[header.len()..];
+    assert_eq!(rows.len(), 8 * 32);
+    // Pixels 1-3 are lit on some plane, so their bits are clear
+    assert_eq!(rows[0], 0b1000_1111);
+    assert!(rows[1..].iter().all(|&byte| byte == 0xFF));
+}
+
+#[test]
+fn png_holds_the_raw_rows() {
+    let palette = Palette::default();
+    let image = encode(&display(), ImageFormat::Png, 3, &palette);
+    assert_eq!(&image[..8], b"\x89PNG\r\n\x1a\n");
+
+    // Walking the chunks, collecting the zlib stream from IDAT
+    let mut rest = &image[8..];
+    let mut kinds = Vec::new();
+    let mut zlib = Vec::new();
+    while !rest.is_empty() {
+        let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let kind = &rest[4..8];
+        let data = &rest[8..8 + len];
+        match kind {
+            b"IHDR" => {
+                assert_eq!(&data[..8], &[0, 0, 0, 192, 0, 0, 0, 96]);
+                assert_eq!(&data[8..], &[8, 2, 0, 0, 0]);
+            }
+            b"IDAT" => zlib.extend_from_slice(data),
+            _ => {}
+        }
+        kinds.push(String::from_utf8(kind.to_vec()).unwrap());
+        rest = &rest[12 + len..];
+    }
+    assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+
+    // Uncompressed deflate blocks: a final flag, then the length and its complement
+    let mut blocks = &zlib[2..zlib.len() - 4];
+    let mut raw = Vec::new();
+    loop {
+        let last = blocks[0] & 1 != 0;
+        let len = u16::from_le_bytes([blocks[1], blocks[2]]);
+        assert_eq!(!len, u16::from_le_bytes([blocks[3], blocks[4]]));
+        raw.extend_from_slice(&blocks[5..5 + len as usize]);
+        blocks = &blocks[5 + len as usize..];
+        if last {
+            break;
+        }
+    }
+    assert!(blocks.is_empty());
+    let stride = 192 * 3 + 1;
+    assert_eq!(raw.len(), stride * 96);
+    assert_eq!(raw[0], 0);
+    assert_eq!(&raw[1 + 3 * 3..1 + 4 * 3], &palette.color(1));
+    assert_eq!(&raw[1 + 9 * 3..1 + 10 * 3], &palette.color(3));
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use chip_8::cpu::{CHIP8, STACK_SIZE};
+use chip_8::random::Random;
+use chip_8::snapshot::Snapshot;
+
+// Save states have to bring a machine back exactly, so it carries on as if it
+// had never been stopped.
+
+// Breakout after a second of play, with the ball in motion
+fn machine() -> CHIP8 {
+    let mut chip = common::breakout(7);
+    for frame in 0..60 {
+        chip.keypad[0x6] = frame % 20 < 10;
+        chip.run_frame().unwrap();
+    }
+    chip
+}
+
+#[test]
+fn snapshots_round_trip() {
+    let snapshot = machine().snapshot();
+    assert_eq!(Snapshot::from_bytes(&snapshot.to_bytes()).unwrap(), snapshot);
+}
+
+#[test]
+fn restored_machines_run_the_same() {
+    let mut original = machine();
+    let mut restored = CHIP8::new();
+    restored.restore(&Snapshot::from_bytes(&original.snapshot().to_bytes()).unwrap());
+    for frame in 0..120 {
+        for chip in [&mut original, &mut restored] {
+            chip.keypad[0x4] = frame % 30 < 15;
+            chip.run_frame().unwrap();
+        }
+    }
+    assert_eq!(restored.display.to_string(), original.display.to_string());
+    assert_eq!((restored.pc, restored.index, restored.variables), (original.pc, original.index, original.variables));
+    assert_eq!(restored.snapshot(), original.snapshot());
+}
+
+#[test]
+fn deep_stacks_are_rejected() {
+    let mut chip = machine();
+    chip.stack.clear();
+    let empty = chip.snapshot().to_bytes();
+    chip.stack = vec![0x200; 3];
+    let mut bytes = chip.snapshot().to_bytes();
+    // The stack depth is the first byte that differs
+    let depth = bytes.iter().zip(&empty).position(|(a, b)| a != b).unwrap();
+    assert_eq!((empty[depth], bytes[depth]), (0, 3));
+    bytes[depth] = STACK_SIZE as u8 + 1;
+    let error = Snapshot::from_bytes(&bytes).unwrap_err();
+    assert_eq!(error.to_string(), "invalid save state: stack too deep");
+}
+
+#[test]
+fn stopped_clocks_are_rejected() {
+    let mut chip = machine();
+    chip.config.ips = 0;
+    let error = Snapshot::from_bytes(&chip.snapshot().to_bytes()).unwrap_err();
+    assert_eq!(error.to_string(), "invalid save state: instructions per second must be at least 1");
+}
+
+#[test]
+fn silent_beeps_are_rejected() {
+    let mut chip = machine();
+    for frequency in [0.0, -440.0, f32::NAN, f32::INFINITY] {
+        chip.config.beep_frequency = frequency;
+        let error = Snapshot::from_bytes(&chip.snapshot().to_bytes()).unwrap_err();
+        assert_eq!(error.to_string(), "invalid save state: beep frequency must be a positive number");
+    }
+}
+
+#[test]
+fn stuck_random_generators_are_rejected() {
+    // A machine that has not run yet still holds the state its seed gave it
+    let mut bytes = common::breakout(7).snapshot().to_bytes();
+    let state = Random::new(7).state().to_le_bytes();
+    let at = bytes.windows(8).position(|window| window == state).unwrap();
+    bytes[at..at + 8].fill(0);
+    let error = Snapshot::from_bytes(&bytes).unwrap_err();
+    assert_eq!(error.to_string(), "invalid save state: random number generator state is zero");
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use chip_8::display::Display;
+use chip_8::palette::Palette;
+use chip_8::video::Video;
+
+// Records a few frames of a changing display and reads the files back. The
+// GIF test decodes the LZW data again to check every picture survives.
+
+// A display with one pixel of each plane combination, shifted along by `n`
+fn display(n: usize) -> Display {
+    let mut display = Display::default();
+    for planes in 0..4 {
+        display.set_pixel(n + planes as usize, n, planes);
+    }
+    display
+}
+
+fn record(name: &str, scale: usize, frames: &[Display]) -> Vec<u8> {
+    let path = common::temp_path(name);
+    let mut video = Video::create(&path, scale, &Palette::default()).unwrap();
+    for frame in frames {
+        video.frame(frame).unwrap();
+    }
+    video.finish().unwrap();
+    assert_eq!(video.frames(), frames.len() as u64);
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    bytes
+}
+
+#[test]
+fn raw_frames_are_rgb() {
+    let bytes = record("frames.rgb", 1, &[display(0), display(1)]);
+    assert_eq!(bytes.len(), 2 * 64 * 32 * 3);
+    let palette = Palette::default();
+    assert_eq!(&bytes[3..6], &palette.color(1));
+    // The second frame's pixels sit one row down and one column right
+    let second = &bytes[64 * 32 * 3..];
+    assert_eq!(&second[(64 + 4) * 3..(64 + 5) * 3], &palette.color(3));
+}
+
+#[test]
+fn y4m_has_a_header_and_three_planes_per_frame() {
+    let bytes = record("frames.y4m", 2, &vec![display(0); 3]);
+    let header = b"YUV4MPEG2 W128 H64 F60:1 Ip A1:1 C444\n";
+    assert_eq!(&bytes[..header.len()], header);
+    let frame = b"FRAME\n".len() + 128 * 64 * 3;
+    assert_eq!(bytes.len(), header.len() + 3 * frame);
+    assert_eq!(&bytes[header.len()..header.len() + 6], b"FRAME\n");
+    // Black is 16, 128, 128 in limited range YUV
+    assert_eq!(bytes[header.len() + 6], 16);
+    assert_eq!(bytes[header.len() + 6 + 128 * 64], 128);
+}
+
+#[test]
+fn hires_frames_keep_the_video_size() {
+    let mut hires = Display::default();
+    hires.set_hires(true);
+    hires.set_pixel(1, 0, 1);
+    let bytes = record("hires.rgb", 2, &[hires]);
+    assert_eq!(bytes.len(), 128 * 64 * 3);
+    assert_eq!(&bytes[..3], &[0, 0, 0]);
+    assert_eq!(&bytes[3..6], &Palette::default().color(1));
+}
+
+#[test]
+fn oversized_gifs_are_rejected() {
+    let path = common::temp_path("huge.gif");
+    let error = Video::create(&path, 1024, &Palette::default()).err().unwrap();
+    assert!(error.to_string().contains("too large"), "{}", error);
+    assert!(!path.exists());
+    let error = Video::create(path.with_extension("rgb"), usize::MAX, &Palette::default()).err().unwrap();
+    assert!(error.to_string().contains("too large"), "{}", error);
+}
+
+#[test]
+fn gif_pictures_decode_with_their_delays() {
+    // 60 frames: ten of a still picture, then a new picture every frame,
+    // which is faster than GIF delays can go, then a still picture again
+    let mut frames = vec![display(0); 10];
+    frames.extend((1..=20).map(display));
+    frames.extend(std::iter::repeat_n(display(21), 30));
+    let bytes = record("frames.gif", 4, &frames);
+
+    let (pictures, delays) = decode_gif(&bytes);
+    assert_eq!(delays.iter().sum::<u32>(), 100);
+    assert!(delays.iter().all(|&delay| delay >= 2));
+    let palette_index = |display: &Display| -> Vec<u8> {
+        (0..128)
+            .flat_map(|y| (0..256).map(move |x| (x, y)))
+            .map(|(x, y)| display.pixel(x / 4, y / 4))
+            .collect()
+    };
+    assert_eq!(pictures[0], palette_index(&display(0)));
+    assert_eq!(pictures.last().unwrap(), &palette_index(&display(21)));
+    assert!(pictures.len() > 2 && pictures.len() < 22);
+}
+
+// Returns the palette indices of every picture and every delay
+fn decode_gif(bytes: &[u8]) -> (Vec<Vec<u8>>, Vec<u32>) {
+    assert_eq!(&bytes[..6], b"GIF89a");
+    assert_eq!(bytes[10], 0x91);
+    let mut at = 13 + 4 * 3;
+    let (mut pictures, mut delays) = (Vec::new(), Vec::new());
+    // Joins the data sub-blocks starting at `at`
+    let blocks = |at: &mut usize| {
+        let mut data = Vec::new();
+        while bytes[*at] != 0 {
+            let len = bytes[*at] as usize;
+            data.extend_from_slice(&bytes[*at + 1..*at + 1 + len]);
+            *at += 1 + len;
+        }
+        *at += 1;
+        data
+    };
+    loop {
+        match bytes[at] {
+            0x3B => break,
+            0x21 => {
+                let label = bytes[at + 1];
+                at += 2;
+                let data = blocks(&mut at);
+                if label == 0xF9 {
+                    delays.push(u16::from_le_bytes([data[1], data[2]]) as u32);
+                }
+            }
+            0x2C => {
+                let size = bytes[at + 10] as u32;
+                at += 11;
+                pictures.push(lzw_decode(&blocks(&mut at), size));
+            }
+            other => panic!("unexpected block {:02X}", other),
+        }
+    }
+    assert_eq!(at, bytes.len() - 1);
+    (pictures, delays)
+}
+
+fn lzw_decode(data: &[u8], min_size: u32) -> Vec<u8> {
+    let clear = 1usize << min_size;
+    let reset = || -> Vec<Vec<u8>> { (0..clear + 2).map(|i| vec![i as u8]).collect() };
+    let (mut table, mut size, mut previous) = (reset(), min_size + 1, None::<Vec<u8>>);
+    let (mut out, mut bit) = (Vec::new(), 0);
+    loop {
+        let code = (0..size as usize).fold(0, |code, i| {
+            let at = bit + i;
+            code | (((data[at / 8] >> (at % 8)) & 1) as usize) << i
+        });
+        bit += size as usize;
+        if code == clear {
+            (table, size, previous) = (reset(), min_size + 1, None);
+            continue;
+        }
+        if code == clear + 1 {
+            return out;
+        }
+        let entry = match &previous {
+            None => table[code].clone(),
+            Some(previous) => {
+                let entry = if code < table.len() {
+                    table[code].clone()
+                } else {
+                    [previous.clone(), vec![previous[0]]].concat()
+                };
+                if table.len() < 4096 {
+                    table.push([previous.clone(), vec![entry[0]]].concat());
+                }
+                if table.len() >= 1 << size && size < 12 {
+                    size += 1;
+                }
+                entry
+            }
+        };
+        out.extend_from_slice(&entry);
+        previous = Some(entry);
+    }
+}